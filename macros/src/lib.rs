@@ -0,0 +1,103 @@
+//! Test harness macros of `zkwasm-rust-sdk`, use them through the re-exports of the sdk.
+//!
+//! The generated code refers to `::zkwasm_rust_sdk` and `::wasm_bindgen`, so crates using the
+//! macros need both as dependencies.
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote, quote_spanned};
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, Error, ItemFn, Path, ReturnType, Token};
+
+/// Register a guest test.
+///
+/// `#[zktest] fn foo() { .. }` keeps `foo` and adds
+/// - a `zktest_foo` export on wasm32, so the test can be proven on its own with
+///   `--function zktest_foo`,
+/// - a native `#[test]` of the same name that runs `foo` against a fresh mock host.
+///
+/// Every test must also be listed in `zktest_main!`, which runs them from `zkmain`; a test that
+/// is not listed fails to compile.
+#[proc_macro_attribute]
+pub fn zktest(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(
+            proc_macro2::TokenStream::from(attr)
+                .into_iter()
+                .next()
+                .unwrap()
+                .span(),
+            "zktest takes no arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+    let f = parse_macro_input!(item as ItemFn);
+    if !f.sig.inputs.is_empty() || !matches!(f.sig.output, ReturnType::Default) {
+        return Error::new_spanned(&f.sig, "zktest functions take no arguments and return ()")
+            .to_compile_error()
+            .into();
+    }
+    let name = &f.sig.ident;
+    let name_str = name.to_string();
+    let export = format_ident!("zktest_{}", name);
+    let listed = quote_spanned! {name.span()=>
+        const _: () = crate::__zktest_registry::#name;
+    };
+    quote! {
+        #f
+
+        // only resolves if `zktest_main!` lists the test
+        #listed
+
+        #[cfg(target_arch = "wasm32")]
+        #[::wasm_bindgen::prelude::wasm_bindgen]
+        pub fn #export() -> i64 {
+            ::zkwasm_rust_sdk::zktest::run(#name_str, #name)
+        }
+
+        #[cfg(all(test, not(target_arch = "wasm32")))]
+        #[test]
+        fn #export() {
+            ::zkwasm_rust_sdk::zktest::run_native(#name_str, #name)
+        }
+    }
+    .into()
+}
+
+/// `zktest_main!(foo, bar)` defines `ZKTESTS`, the list of the given tests, and on wasm32 a
+/// `zkmain` export that runs all of them and reports each on the debug output.
+///
+/// Invoke it once, at the crate root, listing every `#[zktest]` of the crate.
+#[proc_macro]
+pub fn zktest_main(input: TokenStream) -> TokenStream {
+    let tests = match Punctuated::<Path, Token![,]>::parse_terminated.parse(input) {
+        Ok(tests) => tests,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let paths = tests.iter();
+    let idents: Vec<_> = tests
+        .iter()
+        .map(|p| p.segments.last().unwrap().ident.clone())
+        .collect();
+    let names = idents.iter().map(|i| i.to_string());
+    quote! {
+        #[doc(hidden)]
+        #[allow(non_upper_case_globals)]
+        pub mod __zktest_registry {
+            #(pub const #idents: () = ();)*
+        }
+
+        #[allow(dead_code)]
+        pub const ZKTESTS: &[::zkwasm_rust_sdk::zktest::ZkTest] = &[
+            #(::zkwasm_rust_sdk::zktest::ZkTest { name: #names, run: #paths }),*
+        ];
+
+        #[cfg(target_arch = "wasm32")]
+        #[::wasm_bindgen::prelude::wasm_bindgen]
+        pub fn zkmain() -> i64 {
+            ::zkwasm_rust_sdk::zktest::run_all(ZKTESTS)
+        }
+    }
+    .into()
+}
@@ -0,0 +1,151 @@
+//! Leaf payloads of any size.
+//!
+//! A blob is stored in the data cache as a linked list of chunks of at most `CHUNK_LIMBS`
+//! limbs. Every chunk is `[next_hash(4), payload..]` and is cached under its hash in the
+//! `BLOB_CHUNK` domain with `Padding::LengthPrefixed`, the last chunk links to `[0; 4]`. The
+//! hash of the first chunk, the head, commits to the whole blob and is what a merkle leaf
+//! stores; the empty blob has head `[0; 4]`.
+//!
+//! `BlobReader` fetches one chunk at a time and checks it against the hash it was reached
+//! through before handing out its limbs, so only one chunk is held in memory.
+
+use crate::cache;
+use crate::error::{abort_with_host, ZkError};
+use crate::host::{Host, ZkWasmHost};
+use crate::kvpair::KeyValueMap;
+use crate::merkle::{Merkle, MAX_LEAF_DATA};
+use crate::poseidon::{domain, Padding, PoseidonHasher};
+use std::marker::PhantomData;
+
+/// Payload limbs per chunk
+pub const CHUNK_LIMBS: usize = 256;
+
+fn chunk_hash<H: Host>(chunk: &[u64]) -> [u64; 4] {
+    PoseidonHasher::<H>::hash_with_domain_with_host(
+        domain::BLOB_CHUNK,
+        chunk,
+        Padding::LengthPrefixed,
+    )
+}
+
+/// Store `data` as a blob and return its head
+pub fn store_blob(data: &[u64]) -> [u64; 4] {
+    store_blob_with_host::<ZkWasmHost>(data)
+}
+
+pub fn store_blob_with_host<H: Host>(data: &[u64]) -> [u64; 4] {
+    let mut next = [0; 4];
+    let mut chunk = Vec::with_capacity(CHUNK_LIMBS + 4);
+    for payload in data.chunks(CHUNK_LIMBS).rev() {
+        chunk.clear();
+        chunk.extend_from_slice(&next);
+        chunk.extend_from_slice(payload);
+        next = chunk_hash::<H>(&chunk);
+        cache::store_data_with_host::<H>(&next, &chunk);
+    }
+    next
+}
+
+/// Streaming reader of a blob
+pub struct BlobReader<H: Host = ZkWasmHost> {
+    next: [u64; 4],
+    chunk: Vec<u64>,
+    pos: usize,
+    host: PhantomData<H>,
+}
+
+impl BlobReader {
+    pub fn open(head: [u64; 4]) -> Self {
+        Self::open_with_host(head)
+    }
+}
+
+impl<H: Host> BlobReader<H> {
+    pub fn open_with_host(head: [u64; 4]) -> Self {
+        BlobReader {
+            next: head,
+            chunk: vec![],
+            pos: 0,
+            host: PhantomData,
+        }
+    }
+
+    /// Payload of the next chunk, `None` at the end of the blob
+    pub fn next_chunk(&mut self) -> Option<&[u64]> {
+        self.try_next_chunk()
+            .unwrap_or_else(|e| abort_with_host::<H>(e))
+    }
+
+    /// Fallible version of `next_chunk`
+    pub fn try_next_chunk(&mut self) -> Result<Option<&[u64]>, ZkError> {
+        if self.next == [0; 4] {
+            return Ok(None);
+        }
+        self.chunk.resize(CHUNK_LIMBS + 4, 0);
+        let len = cache::try_fetch_data_with_host::<H>(&self.next, &mut self.chunk)? as usize;
+        self.chunk.truncate(len);
+        if len <= 4 {
+            return Err(ZkError::MissingData);
+        }
+        if chunk_hash::<H>(&self.chunk) != self.next {
+            return Err(ZkError::HashMismatch);
+        }
+        self.next.copy_from_slice(&self.chunk[..4]);
+        self.pos = self.chunk.len();
+        Ok(Some(&self.chunk[4..]))
+    }
+
+    /// Read the rest of the blob into memory
+    pub fn read_to_end(&mut self) -> Vec<u64> {
+        self.collect()
+    }
+}
+
+impl<H: Host> Iterator for BlobReader<H> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.pos == self.chunk.len() {
+            self.next_chunk()?;
+            self.pos = 4;
+        }
+        self.pos += 1;
+        Some(self.chunk[self.pos - 1])
+    }
+}
+
+impl<H: Host> Merkle<H> {
+    /// Store `data` as a blob and set its head as the raw leaf at `index`
+    pub fn set_blob(&mut self, index: u32, data: &[u64]) {
+        let head = store_blob_with_host::<H>(data);
+        self.set_simple(index, &head, None);
+    }
+
+    /// Reader of the blob whose head is the raw leaf at `index`
+    pub fn blob_reader(&self, index: u32) -> BlobReader<H> {
+        let mut head = [0; 4];
+        self.get_simple(index, &mut head);
+        BlobReader::open_with_host(head)
+    }
+}
+
+impl<H: Host> KeyValueMap<Merkle<H>> {
+    /// Store `data` as a blob under `key`, for values that do not fit in a leaf
+    pub fn set_blob(&mut self, key: &[u64; 4], data: &[u64]) {
+        let head = store_blob_with_host::<H>(data);
+        self.set(key, &head);
+    }
+
+    /// Reader of the blob stored under `key`, `None` if the key is not set
+    pub fn blob_reader(&self, key: &[u64; 4]) -> Option<BlobReader<H>> {
+        // the smt reads the whole node found in the slot, which may hold a long value of
+        // another key
+        let mut buf = vec![0; MAX_LEAF_DATA];
+        let len = self.get(key, &mut buf);
+        if len == 0 {
+            return None;
+        }
+        H::require(len == 4);
+        Some(BlobReader::open_with_host(buf[..4].try_into().unwrap()))
+    }
+}
@@ -0,0 +1,109 @@
+use crate::host::{Host, ZkWasmHost};
+use std::marker::PhantomData;
+
+/// Bound on the trace rows a batch may use, so that it fits a fixed circuit size.
+///
+/// ```ignore
+/// let mut budget = TraceBudget::new(limit);
+/// while budget.next_item() {
+///     let Some(item) = items.next() else { break };
+///     // process the item
+///     budget.item_done();
+/// }
+/// budget.finish(); // outputs the number of processed items
+/// ```
+///
+/// `next_item` only admits another item if the rows left cover the most expensive item seen so
+/// far (or an explicit estimate, see `next_item_with_estimate`) plus the reserve kept for the
+/// code that runs after the batch. Every item is taken to cost at least one row, so the first
+/// one is not admitted once nothing is left above the reserve. Only items marked with `item_done` count as processed, so an
+/// admitted item the loop breaks out of is not reported.
+pub struct TraceBudget<H: Host = ZkWasmHost> {
+    start: u64,
+    limit: u64,
+    reserve: u64,
+    item_start: Option<u64>,
+    max_item: u64,
+    processed: u64,
+    host: PhantomData<H>,
+}
+
+impl TraceBudget {
+    pub fn new(limit: u64) -> Self {
+        Self::new_with_host(limit)
+    }
+}
+
+impl<H: Host> TraceBudget<H> {
+    /// Budget of `limit` rows from now on
+    pub fn new_with_host(limit: u64) -> Self {
+        TraceBudget {
+            start: H::wasm_trace_size(),
+            limit,
+            reserve: 0,
+            item_start: None,
+            max_item: 0,
+            processed: 0,
+            host: PhantomData,
+        }
+    }
+
+    /// Keep `reserve` rows for the code that runs after the batch
+    pub fn with_reserve(mut self, reserve: u64) -> Self {
+        self.reserve = reserve;
+        self
+    }
+
+    pub fn used(&self) -> u64 {
+        H::wasm_trace_size() - self.start
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used())
+    }
+
+    /// Whether `cost` more rows fit without touching the reserve
+    pub fn fits(&self, cost: u64) -> bool {
+        self.remaining() >= cost.saturating_add(self.reserve)
+    }
+
+    /// Most expensive item so far
+    pub fn max_item_cost(&self) -> u64 {
+        self.max_item
+    }
+
+    pub fn processed(&self) -> u64 {
+        self.processed
+    }
+
+    /// Mark the admitted item as processed and record its cost
+    pub fn item_done(&mut self) {
+        let start = self.item_start.take();
+        H::require(start.is_some());
+        if let Some(start) = start {
+            self.max_item = self.max_item.max(H::wasm_trace_size() - start);
+        }
+        self.processed += 1;
+    }
+
+    /// Start the next item if it is expected to fit, the previous one must be done
+    pub fn next_item(&mut self) -> bool {
+        self.next_item_with_estimate(0)
+    }
+
+    /// Like `next_item`, expecting the item to take at least `estimate` rows
+    pub fn next_item_with_estimate(&mut self, estimate: u64) -> bool {
+        H::require(self.item_start.is_none());
+        if !self.fits(estimate.max(self.max_item).max(1)) {
+            return false;
+        }
+        self.item_start = Some(H::wasm_trace_size());
+        true
+    }
+
+    /// Output the number of processed items as public output and return it
+    pub fn finish(self) -> u64 {
+        H::wasm_output(self.processed);
+        self.processed
+    }
+}
@@ -1,3 +1,4 @@
+#[cfg(not(feature = "mock-host"))]
 extern "C" {
     pub fn cache_set_mode(x: u64);
     pub fn cache_set_hash(x: u64);
@@ -5,35 +6,65 @@ extern "C" {
     pub fn cache_fetch_data() -> u64;
 }
 
+#[cfg(feature = "mock-host")]
+pub use crate::mock::{cache_fetch_data, cache_set_hash, cache_set_mode, cache_store_data};
+
+use crate::error::{abort_with_host, ZkError};
+use crate::host::{Host, ZkWasmHost};
+use crate::session::{Session, SessionGuard};
+
 // It is better for the following to be phantom if data has large size
 pub fn store_data(hash: &[u64; 4], data: &[u64]) {
-    unsafe {
-        cache_set_mode(1);
-        for i in 0..data.len() {
-            cache_store_data(data[i]);
-        }
-        cache_set_hash(hash[0]);
-        cache_set_hash(hash[1]);
-        cache_set_hash(hash[2]);
-        cache_set_hash(hash[3]);
-    }
+    store_data_with_host::<ZkWasmHost>(hash, data)
 }
 
 // It is better for the following to be phantom if data has large size
 pub fn fetch_data(hash: &[u64; 4], data: &mut [u64]) -> u64 {
-    unsafe {
-        cache_set_mode(0);
-        cache_set_hash(hash[0]);
-        cache_set_hash(hash[1]);
-        cache_set_hash(hash[2]);
-        cache_set_hash(hash[3]);
-        let len = cache_fetch_data();
-        if len > 0 {
-            crate::require(len <= data.len() as u64);
-            for i in 0..len as usize {
-                data[i] = cache_fetch_data();
-            }
+    fetch_data_with_host::<ZkWasmHost>(hash, data)
+}
+
+pub fn store_data_with_host<H: Host>(hash: &[u64; 4], data: &[u64]) {
+    let _session = SessionGuard::<H>::begin(Session::Cache);
+    H::cache_set_mode(1);
+    for d in data {
+        H::cache_store_data(*d);
+    }
+    H::cache_set_hash(hash[0]);
+    H::cache_set_hash(hash[1]);
+    H::cache_set_hash(hash[2]);
+    H::cache_set_hash(hash[3]);
+}
+
+pub fn fetch_data_with_host<H: Host>(hash: &[u64; 4], data: &mut [u64]) -> u64 {
+    try_fetch_data_with_host::<H>(hash, data).unwrap_or_else(|e| abort_with_host::<H>(e))
+}
+
+/// Fallible version of `fetch_data`
+pub fn try_fetch_data(hash: &[u64; 4], data: &mut [u64]) -> Result<u64, ZkError> {
+    try_fetch_data_with_host::<ZkWasmHost>(hash, data)
+}
+
+pub fn try_fetch_data_with_host<H: Host>(
+    hash: &[u64; 4],
+    data: &mut [u64],
+) -> Result<u64, ZkError> {
+    let _session = SessionGuard::<H>::begin(Session::Cache);
+    H::cache_set_mode(0);
+    H::cache_set_hash(hash[0]);
+    H::cache_set_hash(hash[1]);
+    H::cache_set_hash(hash[2]);
+    H::cache_set_hash(hash[3]);
+    let len = H::cache_fetch_data();
+    if len > 0 {
+        if len > data.len() as u64 {
+            return Err(ZkError::BufferTooSmall {
+                len,
+                capacity: data.len() as u64,
+            });
+        }
+        for d in data.iter_mut().take(len as usize) {
+            *d = H::cache_fetch_data();
         }
-        return len;
     }
+    Ok(len)
 }
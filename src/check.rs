@@ -0,0 +1,471 @@
+//! Conformance checking of host call sequences.
+//!
+//! The host imports are stateful and only accept particular call sequences, e.g. a merkle
+//! access is an optional `merkle_setdepth`, `merkle_address`, four `merkle_setroot`, four
+//! `merkle_get` or `merkle_set` and four `merkle_getroot`, and a set must be paired with a
+//! preceding get of the same leaf.
+//! Sequences that break these rules usually still run but fail at proving time.
+//!
+//! `Checked<H>` forwards every call to `H` while tracking the state machine of each host
+//! operation and records a `Violation` (with the index of the offending call) for every call
+//! that is not allowed in the current state. Use it in tests, e.g. `Merkle<Checked>` together
+//! with the `mock-host` feature.
+
+use crate::host::{Host, ZkWasmHost};
+use std::cell::RefCell;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A host call that breaks the protocol of its operation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// Number of host calls made through `Checked` before the offending one
+    pub index: u64,
+    pub call: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "host call {} ({}): {}",
+            self.index, self.call, self.reason
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MerkleStep {
+    Idle,
+    Address,
+    SetRoot(usize),
+    Get(usize),
+    Set(usize),
+    GetRoot { set: bool, n: usize },
+}
+
+struct MerkleCheck {
+    step: MerkleStep,
+    /// depth of the next access
+    depth: u64,
+    address: u64,
+    root: [u64; 4],
+    /// (address, root) of the last completed get that was not consumed by a set yet
+    paired_get: Option<(u64, [u64; 4])>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SpongeStep {
+    /// No session opened
+    Closed,
+    /// Absorbing, with the number of limbs pushed since the last `new`
+    Push(usize),
+    /// Reading the digest, with the number of limbs read so far
+    Finalize(usize),
+    /// All limbs of the result have been read
+    Done,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CacheStep {
+    Idle,
+    Store {
+        hashes: usize,
+    },
+    Fetch {
+        hashes: usize,
+    },
+    /// Reading the data of a fetch, `None` until the length has been read
+    Read {
+        remaining: Option<u64>,
+    },
+}
+
+struct Checker {
+    calls: u64,
+    strict: bool,
+    merkle: MerkleCheck,
+    poseidon: SpongeStep,
+    jubjub: SpongeStep,
+    cache: CacheStep,
+    violations: Vec<Violation>,
+}
+
+impl Checker {
+    fn new() -> Self {
+        Checker {
+            calls: 0,
+            strict: false,
+            merkle: MerkleCheck {
+                step: MerkleStep::Idle,
+                depth: 32,
+                address: 0,
+                root: [0; 4],
+                paired_get: None,
+            },
+            poseidon: SpongeStep::Closed,
+            jubjub: SpongeStep::Closed,
+            cache: CacheStep::Idle,
+            violations: vec![],
+        }
+    }
+
+    fn violation(&mut self, call: &'static str, reason: String) {
+        let v = Violation {
+            index: self.calls,
+            call,
+            reason,
+        };
+        if self.strict {
+            panic!("{}", v);
+        }
+        self.violations.push(v);
+    }
+
+    fn merkle(&mut self, call: &'static str, x: u64) {
+        use MerkleStep::*;
+        let m = &mut self.merkle;
+        let next = match (call, m.step) {
+            ("merkle_setdepth", Idle) if x <= 32 => {
+                m.depth = x;
+                Ok(Idle)
+            }
+            ("merkle_address", Idle) => {
+                m.address = x;
+                let depth = std::mem::replace(&mut m.depth, 32);
+                if x >> depth == 0 {
+                    Ok(Address)
+                } else {
+                    Err((
+                        Address,
+                        format!("address {} out of range of depth {}", x, depth),
+                    ))
+                }
+            }
+            ("merkle_setroot", Address) => {
+                m.root[0] = x;
+                Ok(SetRoot(1))
+            }
+            ("merkle_setroot", SetRoot(n)) if n < 4 => {
+                m.root[n] = x;
+                Ok(SetRoot(n + 1))
+            }
+            ("merkle_get", SetRoot(4)) => Ok(Get(1)),
+            ("merkle_get", Get(n)) if n < 4 => Ok(Get(n + 1)),
+            ("merkle_set", SetRoot(4)) => {
+                if m.paired_get.take() == Some((m.address, m.root)) {
+                    Ok(Set(1))
+                } else {
+                    Err((
+                        Set(1),
+                        format!("set of leaf {} without a paired get", m.address),
+                    ))
+                }
+            }
+            ("merkle_set", Set(n)) if n < 4 => Ok(Set(n + 1)),
+            ("merkle_getroot", Get(4)) => Ok(GetRoot { set: false, n: 1 }),
+            ("merkle_getroot", Set(4)) => Ok(GetRoot { set: true, n: 1 }),
+            ("merkle_getroot", GetRoot { set, n }) if n < 4 => {
+                if n == 3 {
+                    m.paired_get = if set { None } else { Some((m.address, m.root)) };
+                    Ok(Idle)
+                } else {
+                    Ok(GetRoot { set, n: n + 1 })
+                }
+            }
+            // resynchronise on the start of the next access to avoid cascading reports
+            (_, step) => Err((
+                if call == "merkle_address" {
+                    m.address = x;
+                    Address
+                } else {
+                    Idle
+                },
+                format!("not allowed {}", Self::merkle_step_name(step)),
+            )),
+        };
+        match next {
+            Ok(step) => self.merkle.step = step,
+            Err((step, reason)) => {
+                self.merkle.step = step;
+                self.violation(call, reason);
+            }
+        }
+    }
+
+    fn merkle_step_name(step: MerkleStep) -> String {
+        match step {
+            MerkleStep::Idle => "outside of a merkle access (expected merkle_address)".into(),
+            MerkleStep::Address => "after merkle_address (expected 4 merkle_setroot)".into(),
+            MerkleStep::SetRoot(n) => format!("after {} of 4 merkle_setroot", n),
+            MerkleStep::Get(n) => format!("after {} of 4 merkle_get", n),
+            MerkleStep::Set(n) => format!("after {} of 4 merkle_set", n),
+            MerkleStep::GetRoot { n, .. } => format!("after {} of 4 merkle_getroot", n),
+        }
+    }
+
+    /// Shared machine of poseidon and babyjubjub: `new(1)` opens a session, `new(0)`
+    /// continues it after a full round has been read, pushes come in rounds of `round`
+    /// limbs and every round is read back with `out` finalize calls.
+    fn sponge(
+        step: SpongeStep,
+        call: &'static str,
+        new: Option<u64>,
+        round: usize,
+        out: usize,
+    ) -> Result<SpongeStep, String> {
+        use SpongeStep::*;
+        match (call.ends_with("_new"), call.ends_with("_push"), step) {
+            (true, _, _) => match (new, step) {
+                (Some(1), Closed | Done | Push(0)) => Ok(Push(0)),
+                (Some(1), Push(n)) => Err(format!("{} limbs pushed but never finalized", n)),
+                (Some(1), Finalize(n)) => Err(format!("only {} of {} limbs read", n, out)),
+                (Some(0), Done) => Ok(Push(0)),
+                (Some(0), _) => Err("continuing a session without a finalized round".into()),
+                (Some(x), _) => Err(format!("unknown mode {}", x)),
+                (None, _) => unreachable!(),
+            },
+            (_, true, Finalize(n)) => Err(format!("push after {} of {} limbs read", n, out)),
+            (_, true, Push(n)) if n < round => Ok(Push(n + 1)),
+            (_, true, Push(_)) => Err(format!("more than {} limbs in one round", round)),
+            (_, true, Closed) => Err("push without a session".into()),
+            (_, true, _) => Err("push after finalize without continuing the session".into()),
+            (_, false, Push(n)) if n == round => Ok(Finalize(1)),
+            (_, false, Push(n)) => Err(format!("finalize after {} of {} limbs", n, round)),
+            (_, false, Finalize(n)) if n + 1 < out => Ok(Finalize(n + 1)),
+            (_, false, Finalize(_)) => Ok(Done),
+            (_, false, Closed) => Err("finalize without a session".into()),
+            (_, false, Done) => Err(format!("more than {} finalize calls", out)),
+        }
+    }
+
+    fn poseidon(&mut self, call: &'static str, new: Option<u64>) {
+        match Self::sponge(self.poseidon, call, new, 32, 4) {
+            Ok(step) => self.poseidon = step,
+            Err(reason) => {
+                self.poseidon = SpongeStep::Closed;
+                self.violation(call, reason)
+            }
+        }
+    }
+
+    fn jubjub(&mut self, call: &'static str, new: Option<u64>) {
+        match Self::sponge(self.jubjub, call, new, 12, 8) {
+            Ok(step) => self.jubjub = step,
+            Err(reason) => {
+                self.jubjub = SpongeStep::Closed;
+                self.violation(call, reason)
+            }
+        }
+    }
+
+    fn cache(&mut self, call: &'static str, x: Option<u64>) {
+        use CacheStep::*;
+        let next = match (call, self.cache) {
+            ("cache_set_mode", Idle | Read { .. }) => match x {
+                Some(1) => Ok(Store { hashes: 0 }),
+                Some(0) => Ok(Fetch { hashes: 0 }),
+                _ => Err(format!("unknown mode {:?}", x)),
+            },
+            ("cache_set_mode", _) => Err("mode changed before the hash was complete".into()),
+            ("cache_store_data", Store { hashes: 0 }) => Ok(Store { hashes: 0 }),
+            ("cache_store_data", _) => Err("store outside of store mode".into()),
+            ("cache_set_hash", Store { hashes: 3 }) => Ok(Idle),
+            ("cache_set_hash", Store { hashes }) => Ok(Store { hashes: hashes + 1 }),
+            ("cache_set_hash", Fetch { hashes: 3 }) => Ok(Read { remaining: None }),
+            ("cache_set_hash", Fetch { hashes }) => Ok(Fetch { hashes: hashes + 1 }),
+            ("cache_set_hash", _) => Err("hash without a mode".into()),
+            ("cache_fetch_data", Read { remaining: None }) => Ok(Read { remaining: x }),
+            ("cache_fetch_data", Read { remaining: Some(n) }) if n > 0 => Ok(Read {
+                remaining: Some(n - 1),
+            }),
+            ("cache_fetch_data", Read { .. }) => Err("fetch past the end of the data".into()),
+            ("cache_fetch_data", _) => Err("fetch before the hash was complete".into()),
+            _ => unreachable!(),
+        };
+        match next {
+            Ok(step) => self.cache = step,
+            Err(reason) => {
+                self.cache = Idle;
+                self.violation(call, reason)
+            }
+        }
+    }
+}
+
+thread_local! {
+    static CHECKER: RefCell<Checker> = RefCell::new(Checker::new());
+}
+
+fn with_checker<R>(f: impl FnOnce(&mut Checker) -> R) -> R {
+    CHECKER.with(|c| {
+        let mut c = c.borrow_mut();
+        let r = f(&mut c);
+        c.calls += 1;
+        r
+    })
+}
+
+/// Forget all protocol state and violations of the current thread
+pub fn reset() {
+    CHECKER.with(|c| *c.borrow_mut() = Checker::new());
+}
+
+/// Panic on the first violation instead of recording it
+pub fn set_strict(strict: bool) {
+    CHECKER.with(|c| c.borrow_mut().strict = strict);
+}
+
+/// Take the violations recorded on this thread so far
+pub fn take_violations() -> Vec<Violation> {
+    CHECKER.with(|c| std::mem::take(&mut c.borrow_mut().violations))
+}
+
+/// Host forwarding to `H` and checking the call sequences
+pub struct Checked<H: Host = ZkWasmHost>(PhantomData<H>);
+
+impl<H: Host> Host for Checked<H> {
+    fn wasm_input(is_public: u32) -> u64 {
+        with_checker(|_| ());
+        H::wasm_input(is_public)
+    }
+    fn wasm_output(v: u64) {
+        with_checker(|_| ());
+        H::wasm_output(v)
+    }
+    fn wasm_read_context() -> u64 {
+        with_checker(|_| ());
+        H::wasm_read_context()
+    }
+    fn wasm_write_context(v: u64) {
+        with_checker(|_| ());
+        H::wasm_write_context(v)
+    }
+    fn require(cond: bool) {
+        with_checker(|_| ());
+        H::require(cond)
+    }
+    fn wasm_dbg(v: u64) {
+        with_checker(|_| ());
+        H::wasm_dbg(v)
+    }
+    fn wasm_dbg_char(v: u64) {
+        with_checker(|_| ());
+        H::wasm_dbg_char(v)
+    }
+    fn wasm_trace_size() -> u64 {
+        with_checker(|_| ());
+        H::wasm_trace_size()
+    }
+
+    fn merkle_setroot(x: u64) {
+        with_checker(|c| c.merkle("merkle_setroot", x));
+        H::merkle_setroot(x)
+    }
+    fn merkle_address(x: u64) {
+        with_checker(|c| c.merkle("merkle_address", x));
+        H::merkle_address(x)
+    }
+    fn merkle_setdepth(x: u64) {
+        with_checker(|c| c.merkle("merkle_setdepth", x));
+        H::merkle_setdepth(x)
+    }
+    fn merkle_set(x: u64) {
+        with_checker(|c| c.merkle("merkle_set", x));
+        H::merkle_set(x)
+    }
+    fn merkle_get() -> u64 {
+        with_checker(|c| c.merkle("merkle_get", 0));
+        H::merkle_get()
+    }
+    fn merkle_getroot() -> u64 {
+        with_checker(|c| c.merkle("merkle_getroot", 0));
+        H::merkle_getroot()
+    }
+    fn merkle_fetch_data() -> u64 {
+        with_checker(|_| ());
+        H::merkle_fetch_data()
+    }
+    fn merkle_put_data(x: u64) {
+        with_checker(|_| ());
+        H::merkle_put_data(x)
+    }
+
+    fn poseidon_new(x: u64) {
+        with_checker(|c| c.poseidon("poseidon_new", Some(x)));
+        H::poseidon_new(x)
+    }
+    fn poseidon_push(x: u64) {
+        with_checker(|c| c.poseidon("poseidon_push", None));
+        H::poseidon_push(x)
+    }
+    fn poseidon_finalize() -> u64 {
+        with_checker(|c| c.poseidon("poseidon_finalize", None));
+        H::poseidon_finalize()
+    }
+
+    fn babyjubjub_sum_new(x: u64) {
+        with_checker(|c| c.jubjub("babyjubjub_sum_new", Some(x)));
+        H::babyjubjub_sum_new(x)
+    }
+    fn babyjubjub_sum_push(x: u64) {
+        with_checker(|c| c.jubjub("babyjubjub_sum_push", None));
+        H::babyjubjub_sum_push(x)
+    }
+    fn babyjubjub_sum_finalize() -> u64 {
+        with_checker(|c| c.jubjub("babyjubjub_sum_finalize", None));
+        H::babyjubjub_sum_finalize()
+    }
+
+    fn cache_set_mode(x: u64) {
+        with_checker(|c| c.cache("cache_set_mode", Some(x)));
+        H::cache_set_mode(x)
+    }
+    fn cache_set_hash(x: u64) {
+        with_checker(|c| c.cache("cache_set_hash", Some(x)));
+        H::cache_set_hash(x)
+    }
+    fn cache_store_data(x: u64) {
+        with_checker(|c| c.cache("cache_store_data", Some(x)));
+        H::cache_store_data(x)
+    }
+    fn cache_fetch_data() -> u64 {
+        // the first fetch after the hash returns the length the rest of the fetch is checked
+        // against, so the inner host has to answer before the check
+        let v = H::cache_fetch_data();
+        with_checker(|c| c.cache("cache_fetch_data", Some(v)));
+        v
+    }
+
+    #[cfg(feature = "witness")]
+    fn wasm_witness_insert(u: u64) {
+        with_checker(|_| ());
+        H::wasm_witness_insert(u)
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_pop() -> u64 {
+        with_checker(|_| ());
+        H::wasm_witness_pop()
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_set_index(x: u64) {
+        with_checker(|_| ());
+        H::wasm_witness_set_index(x)
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_pop() -> u64 {
+        with_checker(|_| ());
+        H::wasm_witness_indexed_pop()
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_insert(x: u64) {
+        with_checker(|_| ());
+        H::wasm_witness_indexed_insert(x)
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_push(x: u64) {
+        with_checker(|_| ());
+        H::wasm_witness_indexed_push(x)
+    }
+}
@@ -0,0 +1,249 @@
+//! Typed state carried between the segments of a continuation.
+//!
+//! A segment saves its state with a `ContextWriter`, which is written to `wasm_write_context`
+//! as
+//!
+//! ```text
+//! version | len | payload (len limbs) | poseidon(version, len, payload) (4 limbs)
+//! ```
+//!
+//! and the next segment restores it with a `ContextReader`, which requires the version to be
+//! the one the segment expects and the hash to match before any value is handed out. The hash
+//! uses `Padding::LengthPrefixed`, so every payload limb, whatever its value, is bound as is.
+
+use crate::host::{Host, ZkWasmHost};
+use crate::kvpair::{KeyValueMap, KeyValueMapU64};
+use crate::merkle::Merkle;
+use crate::poseidon::{HashVersion, Padding, PoseidonHasher};
+use primitive_types::U256;
+use std::marker::PhantomData;
+
+/// Largest payload, in limbs, a context may carry. Readers check the length read from the
+/// host against it before buffering anything.
+pub const MAX_CONTEXT_LEN: u64 = 1 << 20;
+
+/// State that can be saved to and restored from the context
+pub trait ContextState<H: Host = ZkWasmHost>: Sized {
+    fn save(&self, w: &mut ContextWriter<H>);
+    fn restore(r: &mut ContextReader<H>) -> Self;
+}
+
+pub struct ContextWriter<H: Host = ZkWasmHost> {
+    version: u64,
+    payload: Vec<u64>,
+    host: PhantomData<H>,
+}
+
+impl ContextWriter {
+    pub fn new(version: u64) -> Self {
+        Self::new_with_host(version)
+    }
+}
+
+impl<H: Host> ContextWriter<H> {
+    pub fn new_with_host(version: u64) -> Self {
+        ContextWriter {
+            version,
+            payload: vec![],
+            host: PhantomData,
+        }
+    }
+
+    pub fn write_u64(&mut self, v: u64) {
+        self.payload.push(v);
+    }
+
+    /// Length prefixed slice
+    pub fn write_slice(&mut self, data: &[u64]) {
+        self.write_u64(data.len() as u64);
+        self.payload.extend_from_slice(data);
+    }
+
+    pub fn write<T: ContextState<H>>(&mut self, v: &T) {
+        v.save(self);
+    }
+
+    /// Write the tagged and hashed context, the payload must not exceed `MAX_CONTEXT_LEN`
+    pub fn finish(self) {
+        H::require(self.payload.len() as u64 <= MAX_CONTEXT_LEN);
+        let mut data = Vec::with_capacity(self.payload.len() + 2);
+        data.push(self.version);
+        data.push(self.payload.len() as u64);
+        data.extend_from_slice(&self.payload);
+        let hash = PoseidonHasher::<H>::hash_with_host(&data, Padding::LengthPrefixed);
+        for d in data.iter().chain(hash.iter()) {
+            H::wasm_write_context(*d);
+        }
+    }
+}
+
+pub struct ContextReader<H: Host = ZkWasmHost> {
+    payload: Vec<u64>,
+    cursor: usize,
+    host: PhantomData<H>,
+}
+
+impl ContextReader {
+    pub fn load(version: u64) -> Self {
+        Self::load_with_host(version)
+    }
+}
+
+impl<H: Host> ContextReader<H> {
+    /// Read the whole context and check its version and hash
+    pub fn load_with_host(version: u64) -> Self {
+        let tag = H::wasm_read_context();
+        H::require(tag == version);
+        let len = H::wasm_read_context();
+        H::require(len <= MAX_CONTEXT_LEN);
+        let mut data = vec![tag, len];
+        for _ in 0..len {
+            data.push(H::wasm_read_context());
+        }
+        let hash = PoseidonHasher::<H>::hash_with_host(&data, Padding::LengthPrefixed);
+        for h in hash {
+            H::require(h == H::wasm_read_context());
+        }
+        data.drain(0..2);
+        ContextReader {
+            payload: data,
+            cursor: 0,
+            host: PhantomData,
+        }
+    }
+
+    pub fn read_u64(&mut self) -> u64 {
+        H::require(self.cursor < self.payload.len());
+        let v = self.payload[self.cursor];
+        self.cursor += 1;
+        v
+    }
+
+    /// Length prefixed slice
+    pub fn read_vec(&mut self) -> Vec<u64> {
+        let len = self.read_u64() as usize;
+        H::require(len <= self.remaining());
+        let v = self.payload[self.cursor..self.cursor + len].to_vec();
+        self.cursor += len;
+        v
+    }
+
+    pub fn read<T: ContextState<H>>(&mut self) -> T {
+        T::restore(self)
+    }
+
+    /// Number of payload limbs not read yet
+    pub fn remaining(&self) -> usize {
+        self.payload.len() - self.cursor
+    }
+}
+
+impl<H: Host> ContextState<H> for u64 {
+    fn save(&self, w: &mut ContextWriter<H>) {
+        w.write_u64(*self)
+    }
+    fn restore(r: &mut ContextReader<H>) -> Self {
+        r.read_u64()
+    }
+}
+
+impl<H: Host> ContextState<H> for u32 {
+    fn save(&self, w: &mut ContextWriter<H>) {
+        w.write_u64(*self as u64)
+    }
+    fn restore(r: &mut ContextReader<H>) -> Self {
+        let v = r.read_u64();
+        H::require(v <= u32::MAX as u64);
+        v as u32
+    }
+}
+
+impl<H: Host> ContextState<H> for bool {
+    fn save(&self, w: &mut ContextWriter<H>) {
+        w.write_u64(*self as u64)
+    }
+    fn restore(r: &mut ContextReader<H>) -> Self {
+        let v = r.read_u64();
+        H::require(v <= 1);
+        v == 1
+    }
+}
+
+impl<H: Host> ContextState<H> for [u64; 4] {
+    fn save(&self, w: &mut ContextWriter<H>) {
+        for v in self {
+            w.write_u64(*v);
+        }
+    }
+    fn restore(r: &mut ContextReader<H>) -> Self {
+        [r.read_u64(), r.read_u64(), r.read_u64(), r.read_u64()]
+    }
+}
+
+impl<H: Host> ContextState<H> for U256 {
+    fn save(&self, w: &mut ContextWriter<H>) {
+        w.write(&self.0)
+    }
+    fn restore(r: &mut ContextReader<H>) -> Self {
+        U256(r.read())
+    }
+}
+
+impl<H: Host> ContextState<H> for Vec<u64> {
+    fn save(&self, w: &mut ContextWriter<H>) {
+        w.write_slice(self)
+    }
+    fn restore(r: &mut ContextReader<H>) -> Self {
+        r.read_vec()
+    }
+}
+
+impl<H: Host> ContextState<H> for HashVersion {
+    fn save(&self, w: &mut ContextWriter<H>) {
+        w.write_u64(match self {
+            HashVersion::V0 => 0,
+            HashVersion::V1 => 1,
+        })
+    }
+    fn restore(r: &mut ContextReader<H>) -> Self {
+        let v = r.read_u64();
+        H::require(v <= 1);
+        if v == 0 {
+            HashVersion::V0
+        } else {
+            HashVersion::V1
+        }
+    }
+}
+
+/// A merkle tree is carried by its root, depth and hash version
+impl<H: Host> ContextState<H> for Merkle<H> {
+    fn save(&self, w: &mut ContextWriter<H>) {
+        w.write(&self.root);
+        w.write(&self.depth());
+        w.write(&self.hash_version());
+    }
+    fn restore(r: &mut ContextReader<H>) -> Self {
+        let root = r.read();
+        let depth = r.read();
+        Merkle::load_with_depth_with_host(root, depth).with_hash_version(r.read())
+    }
+}
+
+impl<H: Host> ContextState<H> for KeyValueMap<Merkle<H>> {
+    fn save(&self, w: &mut ContextWriter<H>) {
+        w.write(self.merkle())
+    }
+    fn restore(r: &mut ContextReader<H>) -> Self {
+        KeyValueMap::new(r.read())
+    }
+}
+
+impl<H: Host> ContextState<H> for KeyValueMapU64<Merkle<H>> {
+    fn save(&self, w: &mut ContextWriter<H>) {
+        w.write(self.merkle())
+    }
+    fn restore(r: &mut ContextReader<H>) -> Self {
+        KeyValueMapU64::new(r.read())
+    }
+}
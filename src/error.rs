@@ -0,0 +1,108 @@
+use crate::host::{Host, ZkWasmHost};
+use crate::session::Session;
+use std::fmt;
+
+/// Failures of the SDK checks. Every variant has a stable numeric code that is written to the
+/// debug output before aborting, so a failed check can be identified from the trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZkError {
+    /// Leaf data fetched from the cache does not hash to the merkle leaf
+    HashMismatch,
+    /// Non empty merkle leaf without data in the cache
+    MissingData,
+    /// Cached data of `len` limbs does not fit in a buffer of `capacity` limbs
+    BufferTooSmall {
+        len: u64,
+        capacity: u64,
+    },
+    InvalidSignature,
+    /// Witness object outside of the witness area
+    WitnessOutOfBounds,
+    /// A host session was opened while another one is still active
+    SessionOverlap {
+        active: Session,
+        requested: Session,
+    },
+    /// Merkle leaf index outside of a tree of the given depth
+    IndexOutOfRange {
+        index: u64,
+        depth: u32,
+    },
+}
+
+impl ZkError {
+    pub fn code(&self) -> u32 {
+        match self {
+            ZkError::HashMismatch => 1,
+            ZkError::MissingData => 2,
+            ZkError::BufferTooSmall { .. } => 3,
+            ZkError::InvalidSignature => 4,
+            ZkError::WitnessOutOfBounds => 5,
+            ZkError::SessionOverlap { .. } => 6,
+            ZkError::IndexOutOfRange { .. } => 7,
+        }
+    }
+}
+
+impl fmt::Display for ZkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZkError::HashMismatch => write!(f, "leaf data does not match the leaf hash"),
+            ZkError::MissingData => write!(f, "no data for non empty leaf"),
+            ZkError::BufferTooSmall { len, capacity } => {
+                write!(
+                    f,
+                    "data of {} limbs exceeds buffer of {} limbs",
+                    len, capacity
+                )
+            }
+            ZkError::InvalidSignature => write!(f, "invalid signature"),
+            ZkError::WitnessOutOfBounds => write!(f, "witness object out of bounds"),
+            ZkError::SessionOverlap { active, requested } => {
+                write!(f, "{} session during active {} session", requested, active)
+            }
+            ZkError::IndexOutOfRange { index, depth } => {
+                write!(f, "leaf {} out of range of depth {}", index, depth)
+            }
+        }
+    }
+}
+
+/// Write `error <code>: <message>` to the debug output and fail through `require`
+pub fn abort_code_with_host<H: Host>(code: u32, message: &str) -> ! {
+    for c in format!("error {}: {}\n", code, message).bytes() {
+        H::wasm_dbg_char(c as u64);
+    }
+    H::require(false);
+    unreachable!()
+}
+
+pub fn abort_code(code: u32, message: &str) -> ! {
+    abort_code_with_host::<ZkWasmHost>(code, message)
+}
+
+pub fn abort_with_host<H: Host>(err: ZkError) -> ! {
+    abort_code_with_host::<H>(err.code(), &err.to_string())
+}
+
+pub fn abort(err: ZkError) -> ! {
+    abort_with_host::<ZkWasmHost>(err)
+}
+
+/// Abort with a diagnostic unless the condition holds.
+///
+/// `ensure!(cond, err)` reports a `ZkError`, `ensure!(cond, code, "fmt", args..)` reports an
+/// application defined code and message.
+#[macro_export]
+macro_rules! ensure {
+    ($cond:expr, $code:expr, $fmt:literal $(, $args:expr)* $(,)?) => {
+        if !$cond {
+            $crate::error::abort_code($code, &format!($fmt $(, $args)*));
+        }
+    };
+    ($cond:expr, $err:expr $(,)?) => {
+        if !$cond {
+            $crate::error::abort($err);
+        }
+    };
+}
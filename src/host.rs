@@ -0,0 +1,174 @@
+//! Host operations the SDK is built on.
+//!
+//! Every SDK type that talks to the host (`Merkle`, `PoseidonHasher`, the babyjubjub msm and
+//! the data cache) is parameterised by a `Host`. The default `ZkWasmHost` calls the zkWasm
+//! imports directly, or logs every call through `record::Recorder` when the
+//! `record-host-calls` feature is enabled; other implementations can wrap or replace it.
+//! Host state is global in zkWasm, so the trait only has associated functions.
+
+pub trait Host {
+    fn wasm_input(is_public: u32) -> u64;
+    fn wasm_output(v: u64);
+    fn wasm_read_context() -> u64;
+    fn wasm_write_context(v: u64);
+    fn require(cond: bool);
+    fn wasm_dbg(v: u64);
+    fn wasm_dbg_char(v: u64);
+    fn wasm_trace_size() -> u64;
+
+    fn merkle_setroot(x: u64);
+    fn merkle_address(x: u64);
+    fn merkle_setdepth(x: u64);
+    fn merkle_set(x: u64);
+    fn merkle_get() -> u64;
+    fn merkle_getroot() -> u64;
+    fn merkle_fetch_data() -> u64;
+    fn merkle_put_data(x: u64);
+
+    fn poseidon_new(x: u64);
+    fn poseidon_push(x: u64);
+    fn poseidon_finalize() -> u64;
+
+    fn babyjubjub_sum_new(x: u64);
+    fn babyjubjub_sum_push(x: u64);
+    fn babyjubjub_sum_finalize() -> u64;
+
+    fn cache_set_mode(x: u64);
+    fn cache_set_hash(x: u64);
+    fn cache_store_data(x: u64);
+    fn cache_fetch_data() -> u64;
+
+    #[cfg(feature = "witness")]
+    fn wasm_witness_insert(u: u64);
+    #[cfg(feature = "witness")]
+    fn wasm_witness_pop() -> u64;
+    #[cfg(feature = "witness")]
+    fn wasm_witness_set_index(x: u64);
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_pop() -> u64;
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_insert(x: u64);
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_push(x: u64);
+}
+
+/// The imports provided by the zkWasm runtime, called without any instrumentation
+pub struct ImportHost;
+
+/// The host provided by the zkWasm runtime
+#[cfg(not(feature = "record-host-calls"))]
+pub type ZkWasmHost = ImportHost;
+
+/// The host provided by the zkWasm runtime, logging every call
+#[cfg(feature = "record-host-calls")]
+pub type ZkWasmHost = crate::record::Recorder<ImportHost>;
+
+impl Host for ImportHost {
+    fn wasm_input(is_public: u32) -> u64 {
+        unsafe { crate::wasm_input(is_public) }
+    }
+    fn wasm_output(v: u64) {
+        unsafe { crate::wasm_output(v) }
+    }
+    fn wasm_read_context() -> u64 {
+        unsafe { crate::wasm_read_context() }
+    }
+    fn wasm_write_context(v: u64) {
+        unsafe { crate::wasm_write_context(v) }
+    }
+    fn require(cond: bool) {
+        unsafe { crate::require(cond) }
+    }
+    fn wasm_dbg(v: u64) {
+        unsafe { crate::wasm_dbg(v) }
+    }
+    fn wasm_dbg_char(v: u64) {
+        unsafe { crate::wasm_dbg_char(v) }
+    }
+    fn wasm_trace_size() -> u64 {
+        unsafe { crate::wasm_trace_size() }
+    }
+
+    fn merkle_setroot(x: u64) {
+        unsafe { crate::merkle_setroot(x) }
+    }
+    fn merkle_address(x: u64) {
+        unsafe { crate::merkle_address(x) }
+    }
+    fn merkle_setdepth(x: u64) {
+        unsafe { crate::merkle::merkle_setdepth(x) }
+    }
+    fn merkle_set(x: u64) {
+        unsafe { crate::merkle_set(x) }
+    }
+    fn merkle_get() -> u64 {
+        unsafe { crate::merkle_get() }
+    }
+    fn merkle_getroot() -> u64 {
+        unsafe { crate::merkle_getroot() }
+    }
+    fn merkle_fetch_data() -> u64 {
+        unsafe { crate::merkle_fetch_data() }
+    }
+    fn merkle_put_data(x: u64) {
+        unsafe { crate::merkle_put_data(x) }
+    }
+
+    fn poseidon_new(x: u64) {
+        unsafe { crate::poseidon_new(x) }
+    }
+    fn poseidon_push(x: u64) {
+        unsafe { crate::poseidon_push(x) }
+    }
+    fn poseidon_finalize() -> u64 {
+        unsafe { crate::poseidon_finalize() }
+    }
+
+    fn babyjubjub_sum_new(x: u64) {
+        unsafe { crate::babyjubjub_sum_new(x) }
+    }
+    fn babyjubjub_sum_push(x: u64) {
+        unsafe { crate::babyjubjub_sum_push(x) }
+    }
+    fn babyjubjub_sum_finalize() -> u64 {
+        unsafe { crate::babyjubjub_sum_finalize() }
+    }
+
+    fn cache_set_mode(x: u64) {
+        unsafe { crate::cache::cache_set_mode(x) }
+    }
+    fn cache_set_hash(x: u64) {
+        unsafe { crate::cache::cache_set_hash(x) }
+    }
+    fn cache_store_data(x: u64) {
+        unsafe { crate::cache::cache_store_data(x) }
+    }
+    fn cache_fetch_data() -> u64 {
+        unsafe { crate::cache::cache_fetch_data() }
+    }
+
+    #[cfg(feature = "witness")]
+    fn wasm_witness_insert(u: u64) {
+        unsafe { crate::witness::wasm_witness_insert(u) }
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_pop() -> u64 {
+        unsafe { crate::witness::wasm_witness_pop() }
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_set_index(x: u64) {
+        unsafe { crate::witness::wasm_witness_set_index(x) }
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_pop() -> u64 {
+        unsafe { crate::witness::wasm_witness_indexed_pop() }
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_insert(x: u64) {
+        unsafe { crate::witness::wasm_witness_indexed_insert(x) }
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_push(x: u64) {
+        unsafe { crate::witness::wasm_witness_indexed_push(x) }
+    }
+}
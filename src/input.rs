@@ -0,0 +1,136 @@
+use crate::error::{abort_with_host, ZkError};
+use crate::host::{Host, ZkWasmHost};
+use crate::jubjub::{BabyJubjubPoint, JubjubSignature, MODULUS, SUBGROUP_ORDER};
+use primitive_types::U256;
+use std::marker::PhantomData;
+
+/// Typed reader over one of the `wasm_input` channels.
+///
+/// Every value is read as one or more u64 limbs, least significant limb first. Byte vectors and
+/// strings are a length (in bytes) followed by the bytes packed eight per limb in little-endian
+/// order, with the unused bytes of the last limb set to zero. Values out of range fail through
+/// `require`.
+pub struct InputReader<H: Host = ZkWasmHost> {
+    is_public: u32,
+    host: PhantomData<H>,
+}
+
+impl InputReader {
+    /// Reader over the public inputs, which are part of the proof instance
+    pub fn public() -> Self {
+        Self::public_with_host()
+    }
+
+    /// Reader over the private inputs (witness)
+    pub fn private() -> Self {
+        Self::private_with_host()
+    }
+}
+
+impl<H: Host> InputReader<H> {
+    pub fn public_with_host() -> Self {
+        InputReader {
+            is_public: 1,
+            host: PhantomData,
+        }
+    }
+
+    pub fn private_with_host() -> Self {
+        InputReader {
+            is_public: 0,
+            host: PhantomData,
+        }
+    }
+
+    pub fn is_public(&self) -> bool {
+        self.is_public != 0
+    }
+
+    pub fn read_u64(&mut self) -> u64 {
+        H::wasm_input(self.is_public)
+    }
+
+    /// Read a u64 and require it to be smaller than `bound`
+    pub fn read_u64_below(&mut self, bound: u64) -> u64 {
+        let v = self.read_u64();
+        H::require(v < bound);
+        v
+    }
+
+    /// Two's complement of the u64 limb
+    pub fn read_i64(&mut self) -> i64 {
+        self.read_u64() as i64
+    }
+
+    pub fn read_u32(&mut self) -> u32 {
+        let v = self.read_u64();
+        H::require(v <= u32::MAX as u64);
+        v as u32
+    }
+
+    /// Either 0 or 1
+    pub fn read_bool(&mut self) -> bool {
+        let v = self.read_u64();
+        H::require(v <= 1);
+        v == 1
+    }
+
+    pub fn read_limbs(&mut self) -> [u64; 4] {
+        [
+            self.read_u64(),
+            self.read_u64(),
+            self.read_u64(),
+            self.read_u64(),
+        ]
+    }
+
+    pub fn read_u256(&mut self) -> U256 {
+        U256(self.read_limbs())
+    }
+
+    /// A U256 that is a canonical element of the babyjubjub base field
+    pub fn read_field(&mut self) -> U256 {
+        let v = self.read_u256();
+        H::require(v < U256(MODULUS));
+        v
+    }
+
+    /// x then y, both canonical field elements
+    pub fn read_point(&mut self) -> BabyJubjubPoint {
+        BabyJubjubPoint {
+            x: self.read_field(),
+            y: self.read_field(),
+        }
+    }
+
+    /// `sig_r` then `sig_s`, a scalar below `SUBGROUP_ORDER`
+    pub fn read_signature(&mut self) -> JubjubSignature {
+        let sig_r = self.read_point();
+        let sig_s = self.read_limbs();
+        if U256(sig_s) >= U256(SUBGROUP_ORDER) {
+            abort_with_host::<H>(ZkError::InvalidSignature);
+        }
+        JubjubSignature { sig_r, sig_s }
+    }
+
+    /// Length prefixed bytes, the length must not exceed `max_len`
+    pub fn read_bytes(&mut self, max_len: usize) -> Vec<u8> {
+        let len = self.read_u64();
+        H::require(len <= max_len as u64);
+        let len = len as usize;
+        let mut bytes = Vec::with_capacity(len + 7);
+        while bytes.len() < len {
+            bytes.extend_from_slice(&self.read_u64().to_le_bytes());
+        }
+        H::require(bytes[len..].iter().all(|b| *b == 0));
+        bytes.truncate(len);
+        bytes
+    }
+
+    /// Length prefixed UTF-8 string of at most `max_len` bytes
+    pub fn read_string(&mut self, max_len: usize) -> String {
+        let s = String::from_utf8(self.read_bytes(max_len));
+        H::require(s.is_ok());
+        s.unwrap_or_default()
+    }
+}
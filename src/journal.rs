@@ -0,0 +1,83 @@
+use crate::host::{Host, ZkWasmHost};
+use crate::merkle::Merkle;
+use std::collections::BTreeMap;
+
+struct Entry {
+    value: [u64; 4],
+    dirty: bool,
+}
+
+/// Write-back buffer of raw merkle leaves.
+///
+/// Leaves are read from the host once and then served from memory, writes stay in the journal
+/// until `commit`, which sets the final value of every written leaf with one host access each.
+/// The resulting root is the same as with direct `set_simple` calls. Leaves are flushed in index
+/// order so that the host call sequence does not depend on the order of the writes.
+pub struct MerkleJournal<H: Host = ZkWasmHost> {
+    merkle: Merkle<H>,
+    entries: BTreeMap<u32, Entry>,
+}
+
+impl<H: Host> MerkleJournal<H> {
+    pub fn new(merkle: Merkle<H>) -> Self {
+        MerkleJournal {
+            merkle,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Tree as of the last commit
+    pub fn merkle(&self) -> &Merkle<H> {
+        &self.merkle
+    }
+
+    pub fn get(&mut self, index: u32) -> [u64; 4] {
+        let merkle = &self.merkle;
+        self.entries
+            .entry(index)
+            .or_insert_with(|| {
+                let mut value = [0; 4];
+                merkle.get_simple(index, &mut value);
+                Entry {
+                    value,
+                    dirty: false,
+                }
+            })
+            .value
+    }
+
+    pub fn set(&mut self, index: u32, data: &[u64; 4]) {
+        self.entries.insert(
+            index,
+            Entry {
+                value: *data,
+                dirty: true,
+            },
+        );
+    }
+
+    /// Number of leaves written since the last commit
+    pub fn pending(&self) -> usize {
+        self.entries.values().filter(|e| e.dirty).count()
+    }
+
+    /// Write the pending leaves to the tree and return the new root
+    pub fn commit(&mut self) -> [u64; 4] {
+        for (index, entry) in self.entries.iter_mut().filter(|(_, e)| e.dirty) {
+            self.merkle.set_simple(*index, &entry.value, None);
+            entry.dirty = false;
+        }
+        self.merkle.root
+    }
+
+    /// Drop the pending leaves, reads see the tree as of the last commit again
+    pub fn rollback(&mut self) {
+        self.entries.retain(|_, e| !e.dirty);
+    }
+
+    /// Commit and return the tree
+    pub fn finish(mut self) -> Merkle<H> {
+        self.commit();
+        self.merkle
+    }
+}
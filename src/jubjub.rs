@@ -1,7 +1,7 @@
-use crate::babyjubjub_sum_finalize;
-use crate::babyjubjub_sum_new;
-use crate::babyjubjub_sum_push;
-use crate::require;
+use crate::error::{abort_with_host, ZkError};
+use crate::host::{Host, ZkWasmHost};
+use crate::poseidon::{domain, HashVersion, Padding};
+use crate::session::{Session, SessionGuard};
 use primitive_types::U256;
 
 #[derive(Debug, Clone, PartialEq)]
@@ -17,9 +17,17 @@ pub const MODULUS: [u64; 4] = [
     0x30644e72e131a029,
 ];
 
+/// Order of the prime subgroup generated by the base point, the range of signature scalars
+pub const SUBGROUP_ORDER: [u64; 4] = [
+    0x677297dc392126f1,
+    0xab3eedb83920ee0a,
+    0x370a08b6d0302b0b,
+    0x060c89ce5c263405,
+];
+
 pub fn negative_of_fr(b: &[u64; 4]) -> [u64; 4] {
     let mut borrow = 0;
-    let mut a = MODULUS.clone();
+    let mut a = MODULUS;
     for i in 0..4 {
         if a[i] - borrow < b[i] {
             a[i] += (u64::MAX - b[i]) + 1 - borrow;
@@ -34,53 +42,52 @@ pub fn negative_of_fr(b: &[u64; 4]) -> [u64; 4] {
 
 impl BabyJubjubPoint {
     pub fn msm(points: &[(&BabyJubjubPoint, &[u64; 4])]) -> BabyJubjubPoint {
+        Self::msm_with_host::<ZkWasmHost>(points)
+    }
+
+    pub fn msm_with_host<H: Host>(points: &[(&BabyJubjubPoint, &[u64; 4])]) -> BabyJubjubPoint {
+        let _session = SessionGuard::<H>::begin(Session::BabyJubjub);
         let mut len = points.len();
-        unsafe {
-            babyjubjub_sum_new(1u64);
-        }
+        H::babyjubjub_sum_new(1u64);
         for (point, scalar) in points {
-            unsafe {
-                babyjubjub_sum_push(point.x.0[0]);
-                babyjubjub_sum_push(point.x.0[1]);
-                babyjubjub_sum_push(point.x.0[2]);
-                babyjubjub_sum_push(point.x.0[3]);
-                babyjubjub_sum_push(point.y.0[0]);
-                babyjubjub_sum_push(point.y.0[1]);
-                babyjubjub_sum_push(point.y.0[2]);
-                babyjubjub_sum_push(point.y.0[3]);
-                babyjubjub_sum_push(scalar[0]);
-                babyjubjub_sum_push(scalar[1]);
-                babyjubjub_sum_push(scalar[2]);
-                babyjubjub_sum_push(scalar[3]);
-                len -= 1;
-                if len != 0 {
-                    babyjubjub_sum_finalize();
-                    babyjubjub_sum_finalize();
-                    babyjubjub_sum_finalize();
-                    babyjubjub_sum_finalize();
-                    babyjubjub_sum_finalize();
-                    babyjubjub_sum_finalize();
-                    babyjubjub_sum_finalize();
-                    babyjubjub_sum_finalize();
-                    babyjubjub_sum_new(0u64);
-                }
+            H::babyjubjub_sum_push(point.x.0[0]);
+            H::babyjubjub_sum_push(point.x.0[1]);
+            H::babyjubjub_sum_push(point.x.0[2]);
+            H::babyjubjub_sum_push(point.x.0[3]);
+            H::babyjubjub_sum_push(point.y.0[0]);
+            H::babyjubjub_sum_push(point.y.0[1]);
+            H::babyjubjub_sum_push(point.y.0[2]);
+            H::babyjubjub_sum_push(point.y.0[3]);
+            H::babyjubjub_sum_push(scalar[0]);
+            H::babyjubjub_sum_push(scalar[1]);
+            H::babyjubjub_sum_push(scalar[2]);
+            H::babyjubjub_sum_push(scalar[3]);
+            len -= 1;
+            if len != 0 {
+                H::babyjubjub_sum_finalize();
+                H::babyjubjub_sum_finalize();
+                H::babyjubjub_sum_finalize();
+                H::babyjubjub_sum_finalize();
+                H::babyjubjub_sum_finalize();
+                H::babyjubjub_sum_finalize();
+                H::babyjubjub_sum_finalize();
+                H::babyjubjub_sum_finalize();
+                H::babyjubjub_sum_new(0u64);
             }
         }
-        unsafe {
-            BabyJubjubPoint {
-                x: U256([
-                    babyjubjub_sum_finalize(),
-                    babyjubjub_sum_finalize(),
-                    babyjubjub_sum_finalize(),
-                    babyjubjub_sum_finalize(),
-                ]),
-                y: U256([
-                    babyjubjub_sum_finalize(),
-                    babyjubjub_sum_finalize(),
-                    babyjubjub_sum_finalize(),
-                    babyjubjub_sum_finalize(),
-                ]),
-            }
+        BabyJubjubPoint {
+            x: U256([
+                H::babyjubjub_sum_finalize(),
+                H::babyjubjub_sum_finalize(),
+                H::babyjubjub_sum_finalize(),
+                H::babyjubjub_sum_finalize(),
+            ]),
+            y: U256([
+                H::babyjubjub_sum_finalize(),
+                H::babyjubjub_sum_finalize(),
+                H::babyjubjub_sum_finalize(),
+                H::babyjubjub_sum_finalize(),
+            ]),
         }
     }
 }
@@ -113,14 +120,44 @@ const NEG_BASE: BabyJubjubPoint = BabyJubjubPoint {
 const ONE: U256 = U256([1, 0, 0, 0]);
 
 impl JubjubSignature {
+    /// Hash of a message to sign, `msg` is hashed with `Padding::Fixed3of4`, in the `SIGNATURE`
+    /// domain under `HashVersion::V1`
+    pub fn message_hash(version: HashVersion, msg: &[u64]) -> [u64; 4] {
+        Self::message_hash_with_host::<ZkWasmHost>(version, msg)
+    }
+
+    pub fn message_hash_with_host<H: Host>(version: HashVersion, msg: &[u64]) -> [u64; 4] {
+        version.hash_with_host::<H>(domain::SIGNATURE, msg, Padding::Fixed3of4)
+    }
+
     pub fn verify(&self, pk: &BabyJubjubPoint, msghash: &[u64; 4]) {
-        unsafe {
-            let r = BabyJubjubPoint::msm(&[
-                (pk, msghash),
-                (&self.sig_r, &ONE.0),
-                (&NEG_BASE, &self.sig_s),
-            ]);
-            require(r.x.is_zero() && r.y == ONE);
+        self.verify_with_host::<ZkWasmHost>(pk, msghash)
+    }
+
+    pub fn verify_with_host<H: Host>(&self, pk: &BabyJubjubPoint, msghash: &[u64; 4]) {
+        self.try_verify_with_host::<H>(pk, msghash)
+            .unwrap_or_else(|e| abort_with_host::<H>(e))
+    }
+
+    /// Fallible version of `verify`
+    pub fn try_verify(&self, pk: &BabyJubjubPoint, msghash: &[u64; 4]) -> Result<(), ZkError> {
+        self.try_verify_with_host::<ZkWasmHost>(pk, msghash)
+    }
+
+    pub fn try_verify_with_host<H: Host>(
+        &self,
+        pk: &BabyJubjubPoint,
+        msghash: &[u64; 4],
+    ) -> Result<(), ZkError> {
+        let r = BabyJubjubPoint::msm_with_host::<H>(&[
+            (pk, msghash),
+            (&self.sig_r, &ONE.0),
+            (&NEG_BASE, &self.sig_s),
+        ]);
+        if r.x.is_zero() && r.y == ONE {
+            Ok(())
+        } else {
+            Err(ZkError::InvalidSignature)
         }
     }
 }
@@ -10,6 +10,9 @@ pub trait SMTU64 {
 
 /// sparse merkle tree implemented by adding indicators at leafs of each group (32 depth)
 /// to indicate whether the leaf is a data leaf or a root of a deeper merkle tree
+///
+/// Over a `Merkle` of a smaller depth every group has that depth and indexes its leaves with
+/// that many bits of the key.
 pub struct KeyValueMap<S: SMT> {
     merkle: S,
 }
@@ -26,6 +29,10 @@ impl<S: SMT> KeyValueMap<S> {
     pub fn get(&self, key: &[u64; 4], data_buf: &mut [u64]) -> u64 {
         self.merkle.smt_get(key, data_buf)
     }
+    /// The underlying tree, e.g. to read its root
+    pub fn merkle(&self) -> &S {
+        &self.merkle
+    }
 }
 
 pub struct KeyValueMapU64<S: SMTU64> {
@@ -44,4 +51,8 @@ impl<S: SMTU64> KeyValueMapU64<S> {
     pub fn get(&self, key: u64) -> u64 {
         self.merkle.smt_get(key)
     }
+    /// The underlying tree, e.g. to read its root
+    pub fn merkle(&self) -> &S {
+        &self.merkle
+    }
 }
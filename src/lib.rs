@@ -1,5 +1,9 @@
 #![cfg_attr(feature = "witness", feature(ptr_sub_ptr))]
 
+// lets the `zktest` macros refer to `::zkwasm_rust_sdk` from inside the sdk
+extern crate self as zkwasm_rust_sdk;
+
+#[cfg(not(feature = "mock-host"))]
 extern "C" {
     pub fn wasm_input(is_public: u32) -> u64;
     pub fn wasm_output(v: u64);
@@ -8,6 +12,7 @@ extern "C" {
     pub fn require(cond: bool);
     pub fn wasm_dbg(v: u64);
     pub fn wasm_dbg_char(v: u64);
+    pub fn wasm_trace_size() -> u64;
 
     pub fn merkle_setroot(x: u64);
     pub fn merkle_address(x: u64);
@@ -26,18 +31,60 @@ extern "C" {
 
 }
 
+#[cfg(feature = "mock-host")]
+pub use mock::{
+    babyjubjub_sum_finalize, babyjubjub_sum_new, babyjubjub_sum_push, merkle_address,
+    merkle_fetch_data, merkle_get, merkle_getroot, merkle_put_data, merkle_set, merkle_setroot,
+    poseidon_finalize, poseidon_new, poseidon_push, require, wasm_dbg, wasm_dbg_char, wasm_input,
+    wasm_output, wasm_read_context, wasm_trace_size, wasm_write_context,
+};
+
+pub mod blob;
+pub mod budget;
 pub mod cache;
+pub mod check;
+pub mod context;
+pub mod error;
+pub mod host;
+pub mod input;
+pub mod journal;
 pub mod jubjub;
 pub mod kvpair;
+pub mod logger;
 pub mod merkle;
+pub mod output;
+pub mod panic_hook;
 pub mod poseidon;
+pub mod profile;
+pub mod proof;
+pub mod session;
+pub mod transcript;
+
+#[cfg(feature = "mock-host")]
+pub mod mock;
+#[cfg(feature = "offchain")]
+pub mod offchain;
+#[cfg(feature = "record-host-calls")]
+pub mod record;
 
 #[cfg(feature = "witness")]
 pub mod witness;
+pub mod zktest;
 
+pub use blob::BlobReader;
+pub use budget::TraceBudget;
+pub use context::{ContextReader, ContextState, ContextWriter};
+pub use error::ZkError;
+pub use host::{Host, ImportHost, ZkWasmHost};
+pub use input::InputReader;
+pub use journal::MerkleJournal;
 pub use jubjub::*;
 pub use merkle::*;
+pub use output::OutputWriter;
 pub use poseidon::*;
+pub use proof::MerkleProof;
+pub use transcript::Transcript;
+pub use zkwasm_rust_sdk_macros::{zktest, zktest_main};
 
 #[cfg(feature = "witness")]
 pub use witness::*;
@@ -53,18 +100,30 @@ pub fn wasm_dbg_str(s: &str) {
 
 #[macro_export]
 macro_rules! dbg {
-    ($fmt:literal $(, $args:tt)* $(,)?)
+    ($fmt:literal $(, $args:expr)* $(,)?)
         => {
-            let _ = $crate::wasm_dbg_str(&format!($fmt $(, $args)*));
+            if $crate::logger::ENABLED {
+                $crate::wasm_dbg_str(&format!($fmt $(, $args)*));
+            }
         };
 }
 #[macro_export]
 macro_rules! dbgln {
-    ($fmt:literal $(, $args:tt)* $(,)?) => {
-        $dbg!($fmt $(, $args)*);
-        $dbg!("\n");
+    ($fmt:literal $(, $args:expr)* $(,)?) => {
+        $crate::dbg!($fmt $(, $args)*);
+        $crate::dbg!("\n");
     };
 }
 
-#[cfg(feature = "wasmbind")]
+#[cfg(any(feature = "wasmbind", all(test, feature = "mock-host")))]
 mod test;
+
+#[cfg(any(feature = "wasmbind", all(test, feature = "mock-host")))]
+zktest_main!(
+    test::test_merkle,
+    test::test_jubjub,
+    test::test_poseidon,
+    test::test_kvpair,
+    test::test_kvpair_u64,
+    test::test_witness
+);
@@ -0,0 +1,124 @@
+//! Level filtered logging through the debug output of the host.
+//!
+//! ```ignore
+//! log_info!("set leaf {}", index; root = hex(&merkle.root), len = data.len());
+//! ```
+//!
+//! writes `[INFO] set leaf 3 root=0x... len=2` followed by a newline through `wasm_dbg_char`.
+//! Fields after the `;` are `key = value` pairs of `Display` values, `hex` formats limbs
+//! such as hashes and `U256`s. Records above `max_level()` are skipped at runtime and with the
+//! `no-debug` feature every logging macro (and `dbg!`/`dbgln!`) compiles to nothing.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Whether debug output is compiled in at all
+pub const ENABLED: bool = cfg!(not(feature = "no-debug"));
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Records more verbose than `level` are dropped, the default is `Level::Info`
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn max_level() -> Level {
+    match MAX_LEVEL.load(Ordering::Relaxed) {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+pub fn enabled(level: Level) -> bool {
+    ENABLED && level <= max_level()
+}
+
+/// Hex formatting of little-endian limbs, most significant limb first
+pub struct Hex<'a>(pub &'a [u64]);
+
+pub fn hex(limbs: &[u64]) -> Hex<'_> {
+    Hex(limbs)
+}
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x")?;
+        for l in self.0.iter().rev() {
+            write!(f, "{:016x}", l)?;
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn write_record(level: Level, args: fmt::Arguments, fields: &[(&str, &dyn fmt::Display)]) {
+    let mut line = format!("[{}] {}", level.as_str(), args);
+    for (key, value) in fields {
+        line += &format!(" {}={}", key, value);
+    }
+    line.push('\n');
+    crate::wasm_dbg_str(&line);
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $fmt:literal $(, $args:expr)* $(; $($key:ident = $value:expr),+)? $(,)?) => {
+        if $crate::logger::enabled($level) {
+            $crate::logger::write_record(
+                $level,
+                format_args!($fmt $(, $args)*),
+                &[$($((stringify!($key), &$value as &dyn ::core::fmt::Display)),+)?],
+            );
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! log_error {
+    ($($t:tt)*) => { $crate::log!($crate::logger::Level::Error, $($t)*) };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    ($($t:tt)*) => { $crate::log!($crate::logger::Level::Warn, $($t)*) };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    ($($t:tt)*) => { $crate::log!($crate::logger::Level::Info, $($t)*) };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    ($($t:tt)*) => { $crate::log!($crate::logger::Level::Debug, $($t)*) };
+}
+
+#[macro_export]
+macro_rules! log_trace {
+    ($($t:tt)*) => { $crate::log!($crate::logger::Level::Trace, $($t)*) };
+}
@@ -1,3 +1,4 @@
+#[cfg(not(feature = "mock-host"))]
 extern "C" {
     pub fn merkle_setroot(x: u64);
     pub fn merkle_address(x: u64);
@@ -6,155 +7,362 @@ extern "C" {
     pub fn merkle_getroot() -> u64;
 }
 
+#[cfg(feature = "mock-host")]
+pub use crate::mock::{
+    merkle_address, merkle_get, merkle_getroot, merkle_set, merkle_setdepth, merkle_setroot,
+};
+
+/// Depth of the tree of the next merkle access.
+///
+/// The zkWasm host does not expose a depth parameter yet and only serves trees of depth 32, so
+/// any other depth fails; the mock host supports every depth up to 32.
+///
+/// # Safety
+///
+/// Same contract as the merkle imports.
+#[cfg(not(feature = "mock-host"))]
+pub unsafe fn merkle_setdepth(x: u64) {
+    crate::require(x == MAX_DEPTH as u64);
+}
+
 use crate::cache;
+use crate::error::{abort_with_host, ZkError};
+use crate::host::{Host, ZkWasmHost};
 use crate::kvpair::{SMT, SMTU64};
-use crate::poseidon::PoseidonHasher;
-use crate::require;
+use crate::poseidon::{domain, HashVersion, Padding};
+use crate::session::{Session, SessionGuard};
+use std::marker::PhantomData;
 
-pub struct Merkle {
+pub struct Merkle<H: Host = ZkWasmHost> {
     pub root: [u64; 4],
+    depth: u32,
+    version: HashVersion,
+    host: PhantomData<H>,
 }
 
+/// Largest leaf data `get` can receive into the sdk owned buffers
+pub const MAX_LEAF_DATA: usize = 1024;
+
 // buf to receive max size of merkle leaf data node
-static mut DATA_NODE_BUF: [u64; 1024] = [0; 1024];
+static mut DATA_NODE_BUF: [u64; MAX_LEAF_DATA] = [0; MAX_LEAF_DATA];
+
+/// Depth of the trees of `Merkle::new`, the largest one the host supports
+pub const MAX_DEPTH: u32 = 32;
+
+/// `EMPTY_ROOTS[d]` is the root of a tree of depth `d` with every leaf set to zero
+#[rustfmt::skip]
+pub const EMPTY_ROOTS: [[u64; 4]; MAX_DEPTH as usize + 1] = [
+    [13451132345359348448, 3653558309459482447, 16802055810060324772, 1576172549342589607],
+    [4119138998488014996, 5420788553405797906, 5595376905221316202, 1409350775573166673],
+    [4921706057622397477, 8670179624867550922, 9997277186934357875, 2641457427586660772],
+    [16896339527466932703, 6630983454998331132, 9246980911386338265, 2071559277499090611],
+    [10219030269956864545, 5249326850860415451, 5779734375203336458, 3163679541925800662],
+    [3321432386612118423, 14157050724907736536, 14455012482429398162, 3166797233807462061],
+    [12677010926563581132, 1750724365479114099, 11637610200528524935, 992527434047711166],
+    [15631796833014930930, 1852940290564317908, 3910017959858316104, 1408232999019975691],
+    [13940749009328808725, 13665176514793917811, 15701865815221036388, 717637194140744063],
+    [3684474252349112237, 8661016934418776590, 15100695408136997123, 2157390095276424555],
+    [4628970425326882931, 13649694299680306101, 17715871298477449256, 1655488845256895253],
+    [5275497673650965508, 8469309539352136475, 10360385237816432648, 3181736010601815399],
+    [9623977101287444756, 6541907550298057761, 8952226393376903993, 3076395674877182187],
+    [6668349054905028536, 187026406787189990, 1605914381526089479, 3117594445500105258],
+    [17518110397435052011, 3526456246378504010, 6341911655360443584, 3063797168924028550],
+    [7619716829576652775, 14061556058708873434, 1010705986984048654, 253790676160860669],
+    [7193944756609978800, 9554354913472489931, 8114930282272055845, 618618072030409899],
+    [11795755926641183113, 12645446688585708081, 6537894788948833424, 3412461978616985921],
+    [2121331136503632011, 9076717334253317469, 16318509125328827397, 2999679070733441700],
+    [8914041923753630727, 4129785230215440362, 16146239664574105852, 2946532188764787239],
+    [15813456722404825931, 7678924220021043530, 12439956614816689370, 997795918919445971],
+    [17814372164074317074, 12673651046533214804, 5070740327365867549, 391226103870347498],
+    [8409359693356994192, 4785589422767793284, 2683923846568221291, 1710532089628039277],
+    [15214839410038149781, 13489854096592999221, 13768899090726171090, 2746100241743345937],
+    [1175013778080180030, 4874995493261619248, 14320788625230230790, 2127552131279191699],
+    [10111150826503189, 4085667109653622043, 4328402269429267831, 2490141551239404801],
+    [4335201465254651299, 3457148867581014315, 9763894429558868229, 1963905698134727684],
+    [13784964628685439897, 3590297518131851262, 5743561508289233952, 2462915921095954353],
+    [12767816434494365982, 10748654856705355952, 4792110018341961150, 2061688934701088583],
+    [3473136332723026584, 5196745827820076638, 16940762699671790996, 606788050132247273],
+    [8305269108977857527, 16642133871446937037, 9958192294737887401, 2655483229521306306],
+    [8572487814795784066, 12925617438271993288, 6305447759006135729, 1839166411678415469],
+    [14789582351289948625, 10919489180071018470, 10309858136294505219, 2839580074036780766],
+];
+
+const DEFAULT_ROOT: [u64; 4] = EMPTY_ROOTS[MAX_DEPTH as usize];
 
 impl Merkle {
     /// New Merkle with initial root hash
     /// set root with move to avoid copy
     pub fn load(root: [u64; 4]) -> Self {
-        Merkle { root }
+        Self::load_with_host(root)
     }
 
     pub fn new() -> Self {
-        //THE following is the depth=31, 32 level merkle root default
-        let root = [
-            14789582351289948625,
-            10919489180071018470,
-            10309858136294505219,
-            2839580074036780766,
-        ];
-        Merkle { root }
+        Self::new_with_host()
+    }
+
+    /// Empty tree of depth `depth` with leaf indices below `2^depth`
+    pub fn with_depth(depth: u32) -> Self {
+        Self::with_depth_with_host(depth)
+    }
+
+    pub fn load_with_depth(root: [u64; 4], depth: u32) -> Self {
+        Self::load_with_depth_with_host(root, depth)
+    }
+}
+
+impl<H: Host> Default for Merkle<H> {
+    fn default() -> Self {
+        Self::new_with_host()
+    }
+}
+
+impl<H: Host> Merkle<H> {
+    /// New Merkle over host `H` with initial root hash
+    pub fn load_with_host(root: [u64; 4]) -> Self {
+        Merkle {
+            root,
+            depth: MAX_DEPTH,
+            version: HashVersion::V0,
+            host: PhantomData,
+        }
+    }
+
+    pub fn load_with_depth_with_host(root: [u64; 4], depth: u32) -> Self {
+        H::require(depth <= MAX_DEPTH);
+        Merkle {
+            depth,
+            ..Self::load_with_host(root)
+        }
+    }
+
+    pub fn with_depth_with_host(depth: u32) -> Self {
+        H::require(depth <= MAX_DEPTH);
+        Self::load_with_depth_with_host(EMPTY_ROOTS[depth as usize], depth)
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    /// Hash leaf data (and `KeyValueMap` entries) with `version`.
+    ///
+    /// Trees written with one version can only be read back with the same one.
+    pub fn with_hash_version(mut self, version: HashVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn hash_version(&self) -> HashVersion {
+        self.version
+    }
+
+    /// Tree with the given root sharing the configuration of `self`
+    fn sub_tree(&self, root: [u64; 4]) -> Self {
+        Merkle { root, ..*self }
+    }
+
+    /// Point the host at leaf `index`, checking it is inside the tree
+    fn address(&self, index: u32) {
+        if self.depth != MAX_DEPTH {
+            if (index as u64) >> self.depth != 0 {
+                abort_with_host::<H>(ZkError::IndexOutOfRange {
+                    index: index as u64,
+                    depth: self.depth,
+                });
+            }
+            H::merkle_setdepth(self.depth as u64);
+        }
+        H::merkle_address(index as u64);
+    }
+
+    pub fn new_with_host() -> Self {
+        Self::load_with_host(DEFAULT_ROOT)
     }
 
     /// Get the raw leaf data of a merkle subtree
     pub fn get_simple(&self, index: u32, data: &mut [u64; 4]) {
-        unsafe {
-            merkle_address(index as u64); // build in merkle address has default depth 32
-
-            merkle_setroot(self.root[0]);
-            merkle_setroot(self.root[1]);
-            merkle_setroot(self.root[2]);
-            merkle_setroot(self.root[3]);
-
-            data[0] = merkle_get();
-            data[1] = merkle_get();
-            data[2] = merkle_get();
-            data[3] = merkle_get();
-
-            //enforce root does not change
-            merkle_getroot();
-            merkle_getroot();
-            merkle_getroot();
-            merkle_getroot();
-        }
+        let _session = SessionGuard::<H>::begin(Session::Merkle);
+        self.address(index);
+
+        H::merkle_setroot(self.root[0]);
+        H::merkle_setroot(self.root[1]);
+        H::merkle_setroot(self.root[2]);
+        H::merkle_setroot(self.root[3]);
+
+        data[0] = H::merkle_get();
+        data[1] = H::merkle_get();
+        data[2] = H::merkle_get();
+        data[3] = H::merkle_get();
+
+        //enforce root does not change
+        H::merkle_getroot();
+        H::merkle_getroot();
+        H::merkle_getroot();
+        H::merkle_getroot();
     }
 
     /// Set the raw leaf data of a merkle subtree but does enforced the get/set pair convention
+    ///
+    /// # Safety
+    ///
+    /// The host expects every set to be preceded by a get of the same leaf; the caller must
+    /// have issued that get already.
     pub unsafe fn set_simple_unsafe(&mut self, index: u32, data: &[u64; 4]) {
-        unsafe {
-            // perform the set
-            merkle_address(index as u64);
-
-            merkle_setroot(self.root[0]);
-            merkle_setroot(self.root[1]);
-            merkle_setroot(self.root[2]);
-            merkle_setroot(self.root[3]);
-
-            merkle_set(data[0]);
-            merkle_set(data[1]);
-            merkle_set(data[2]);
-            merkle_set(data[3]);
-
-            self.root[0] = merkle_getroot();
-            self.root[1] = merkle_getroot();
-            self.root[2] = merkle_getroot();
-            self.root[3] = merkle_getroot();
-        }
+        let _session = SessionGuard::<H>::begin(Session::Merkle);
+        // perform the set
+        self.address(index);
+
+        H::merkle_setroot(self.root[0]);
+        H::merkle_setroot(self.root[1]);
+        H::merkle_setroot(self.root[2]);
+        H::merkle_setroot(self.root[3]);
+
+        H::merkle_set(data[0]);
+        H::merkle_set(data[1]);
+        H::merkle_set(data[2]);
+        H::merkle_set(data[3]);
+
+        self.root[0] = H::merkle_getroot();
+        self.root[1] = H::merkle_getroot();
+        self.root[2] = H::merkle_getroot();
+        self.root[3] = H::merkle_getroot();
     }
 
     /// Set the raw leaf data of a merkle subtree
     pub fn set_simple(&mut self, index: u32, data: &[u64; 4], hint: Option<&[u64; 4]>) {
+        let session = SessionGuard::<H>::begin(Session::Merkle);
         // place a dummy get for merkle proof convension
-        unsafe {
-            merkle_address(index as u64);
-            merkle_setroot(self.root[0]);
-            merkle_setroot(self.root[1]);
-            merkle_setroot(self.root[2]);
-            merkle_setroot(self.root[3]);
-        }
+        self.address(index);
+        H::merkle_setroot(self.root[0]);
+        H::merkle_setroot(self.root[1]);
+        H::merkle_setroot(self.root[2]);
+        H::merkle_setroot(self.root[3]);
         if let Some(hint_data) = hint {
-            unsafe {
-                require(hint_data[0] == merkle_get());
-                require(hint_data[1] == merkle_get());
-                require(hint_data[2] == merkle_get());
-                require(hint_data[3] == merkle_get());
-            }
+            H::require(hint_data[0] == H::merkle_get());
+            H::require(hint_data[1] == H::merkle_get());
+            H::require(hint_data[2] == H::merkle_get());
+            H::require(hint_data[3] == H::merkle_get());
         } else {
-            unsafe {
-                merkle_get();
-                merkle_get();
-                merkle_get();
-                merkle_get();
-            }
+            H::merkle_get();
+            H::merkle_get();
+            H::merkle_get();
+            H::merkle_get();
         }
+        //enforce root does not change
+        H::merkle_getroot();
+        H::merkle_getroot();
+        H::merkle_getroot();
+        H::merkle_getroot();
+        drop(session);
+
+        // perform the set
         unsafe {
-            //enforce root does not change
-            merkle_getroot();
-            merkle_getroot();
-            merkle_getroot();
-            merkle_getroot();
-
-            // perform the set
             self.set_simple_unsafe(index, data);
         }
     }
 
-    pub fn get(&self, index: u32, data: &mut [u64], hash: &mut [u64; 4], pad: bool) -> u64 {
+    pub fn get(&self, index: u32, data: &mut [u64], hash: &mut [u64; 4], pad: Padding) -> u64 {
+        self.try_get(index, data, hash, pad)
+            .unwrap_or_else(|e| abort_with_host::<H>(e))
+    }
+
+    /// Fallible version of `get`
+    pub fn try_get(
+        &self,
+        index: u32,
+        data: &mut [u64],
+        hash: &mut [u64; 4],
+        pad: Padding,
+    ) -> Result<u64, ZkError> {
+        self.try_get_in(domain::MERKLE_LEAF, index, data, hash, pad)
+    }
+
+    fn try_get_in(
+        &self,
+        tag: u64,
+        index: u32,
+        data: &mut [u64],
+        hash: &mut [u64; 4],
+        pad: Padding,
+    ) -> Result<u64, ZkError> {
         self.get_simple(index, hash);
-        let len = cache::fetch_data(&hash, data);
+        let len = cache::try_fetch_data_with_host::<H>(hash, data)?;
         if len > 0 {
             // FIXME: avoid copy here
-            let hash_check = PoseidonHasher::hash(&data[0..len as usize], pad);
-            unsafe {
-                require(hash[0] == hash_check[0]);
-                require(hash[1] == hash_check[1]);
-                require(hash[2] == hash_check[2]);
-                require(hash[3] == hash_check[3]);
-            }
-        } else {
-            unsafe {
-                require(hash[0] == 0);
-                require(hash[1] == 0);
-                require(hash[2] == 0);
-                require(hash[3] == 0);
+            let hash_check = self
+                .version
+                .hash_with_host::<H>(tag, &data[0..len as usize], pad);
+            if *hash != hash_check {
+                return Err(ZkError::HashMismatch);
             }
+        } else if *hash != [0; 4] {
+            return Err(ZkError::MissingData);
         }
-        len
+        Ok(len)
     }
 
     /// safe version of set which enforces a get before set
-    pub fn set(&mut self, index: u32, data: &[u64], pad: bool, hint: Option<&[u64; 4]>) {
-        let hash = PoseidonHasher::hash(data, pad);
-        cache::store_data(&hash, data);
+    pub fn set(&mut self, index: u32, data: &[u64], pad: Padding, hint: Option<&[u64; 4]>) {
+        self.set_in(domain::MERKLE_LEAF, index, data, pad, hint)
+    }
+
+    fn set_in(
+        &mut self,
+        tag: u64,
+        index: u32,
+        data: &[u64],
+        pad: Padding,
+        hint: Option<&[u64; 4]>,
+    ) {
+        let hash = self.version.hash_with_host::<H>(tag, data, pad);
+        cache::store_data_with_host::<H>(&hash, data);
         self.set_simple(index, &hash, hint);
     }
 
     /// unsafe version of set which does not enforce the get/set pair convention
-    pub unsafe fn set_unsafe(&mut self, index: u32, data: &[u64], pad: bool) {
-        let hash = PoseidonHasher::hash(data, pad);
-        cache::store_data(&hash, data);
+    ///
+    /// # Safety
+    ///
+    /// Same contract as [`Merkle::set_simple_unsafe`].
+    pub unsafe fn set_unsafe(&mut self, index: u32, data: &[u64], pad: Padding) {
+        self.set_unsafe_in(domain::MERKLE_LEAF, index, data, pad)
+    }
+
+    unsafe fn set_unsafe_in(&mut self, tag: u64, index: u32, data: &[u64], pad: Padding) {
+        let hash = self.version.hash_with_host::<H>(tag, data, pad);
+        cache::store_data_with_host::<H>(&hash, data);
         self.set_simple_unsafe(index, &hash);
     }
+
+    /// Replace the raw leaf data at `index` by `f(old)` with a single paired get and set.
+    ///
+    /// `f` runs between the get and the set and must not use the host merkle, poseidon,
+    /// babyjubjub or cache functions; doing so aborts with `ZkError::SessionOverlap`.
+    pub fn update_simple(&mut self, index: u32, f: impl FnOnce(&[u64; 4]) -> [u64; 4]) -> [u64; 4] {
+        let mut old = [0; 4];
+        self.get_simple(index, &mut old);
+        let session = SessionGuard::<H>::begin(Session::Merkle);
+        let new = f(&old);
+        drop(session);
+        unsafe {
+            self.set_simple_unsafe(index, &new);
+        }
+        new
+    }
+
+    /// Same as `update_simple` for leaf data set with `set`, `f` receives the old data (empty
+    /// for an empty leaf) and returns the new data.
+    pub fn update(&mut self, index: u32, pad: Padding, f: impl FnOnce(&[u64]) -> Vec<u64>) {
+        let mut old = vec![0; MAX_LEAF_DATA];
+        let mut hash = [0; 4];
+        let len = self.get(index, &mut old, &mut hash, pad);
+        let session = SessionGuard::<H>::begin(Session::Merkle);
+        let new = f(&old[..len as usize]);
+        drop(session);
+        unsafe {
+            self.set_unsafe(index, &new, pad);
+        }
+    }
 }
 
 const LEAF_NODE: u64 = 0;
@@ -181,56 +389,85 @@ fn set_smt_data(node_buf: &mut [u64], t: u64, key: &[u64], data: &[u64]) {
     node_buf[2] = key[1];
     node_buf[3] = key[2];
     node_buf[4] = key[3];
-    for i in 0..data.len() {
-        node_buf[5 + i] = data[i];
-    }
+    node_buf[5..5 + data.len()].copy_from_slice(data);
 }
 
-impl Merkle {
+impl<H: Host> Merkle<H> {
+    /// Leaf of `key` in the tree of level `path_index`: every level takes the next `depth` bits
+    /// of the key, starting from the least significant ones
+    fn smt_local_index(&self, key: &[u64], path_index: usize) -> u32 {
+        let bits = self.depth as usize;
+        let offset = path_index * bits;
+        H::require(bits > 0 && offset < 256);
+        let (limb, shift) = (offset / 64, offset % 64);
+        let mut v = key[limb] >> shift;
+        if shift + bits > 64 && limb + 1 < 4 {
+            v |= key[limb + 1] << (64 - shift);
+        }
+        (v & ((1u64 << bits) - 1)) as u32
+    }
+
     fn smt_get_local(&self, key: &[u64; 4], path_index: usize, data: &mut [u64]) -> u64 {
         //crate::dbg!("start smt_get_local {}\n", path_index);
-        unsafe { require(path_index < 8) };
-        let local_index = (key[path_index / 2] >> (32 * (path_index % 2))) as u32;
+        let local_index = self.smt_local_index(key, path_index);
         let mut hash = [0; 4];
-        // pad is true since the leaf might the root of a sub merkle
-        let len = self.get(local_index, data, &mut hash, true);
+        // legacy padding since the leaf might the root of a sub merkle
+        let len = self
+            .try_get_in(
+                domain::SMT_NODE,
+                local_index,
+                data,
+                &mut hash,
+                Padding::Legacy,
+            )
+            .unwrap_or_else(|e| abort_with_host::<H>(e));
         if len == 0 {
             // no node was find
-            return 0;
+            0
         } else {
             //crate::dbg!("smt_get_local with data {:?}\n", data);
             if (data[0] & 0x1) == LEAF_NODE {
                 //crate::dbg!("smt_get_local is leaf\n");
                 if data_matches_key(data, key) {
-                    for i in 0..(len - 5) {
-                        data[i as usize] = data[i as usize + 5]
-                    }
-                    return len - 5;
+                    data.copy_within(5..len as usize, 0);
+                    len - 5
                 } else {
                     // not hit and return len = 0
-                    return 0;
+                    0
                 }
             } else {
                 //crate::dbg!("smt_get_local is node: continue in sub merkle\n");
-                unsafe { require((data[0] & 0x1) == TREE_NODE) };
-                let sub_merkle = Merkle::load(data[1..5].try_into().unwrap());
+                H::require((data[0] & 0x1) == TREE_NODE);
+                let sub_merkle = self.sub_tree(data[1..5].try_into().unwrap());
                 sub_merkle.smt_get_local(key, path_index + 1, data)
             }
         }
     }
 
     fn smt_set_local(&mut self, key: &[u64], path_index: usize, data: &[u64]) {
-        unsafe { require(path_index < 8) };
-        let local_index = (key[path_index / 2] >> (32 * (path_index % 2))) as u32;
-        let node_buf = unsafe { DATA_NODE_BUF.as_mut_slice() };
+        let local_index = self.smt_local_index(key, path_index);
+        let node_buf = unsafe { &mut *std::ptr::addr_of_mut!(DATA_NODE_BUF) };
         let mut hint_hash = [0; 4];
-        let len = self.get(local_index, node_buf, &mut hint_hash, true);
+        let len = self
+            .try_get_in(
+                domain::SMT_NODE,
+                local_index,
+                node_buf,
+                &mut hint_hash,
+                Padding::Legacy,
+            )
+            .unwrap_or_else(|e| abort_with_host::<H>(e));
         if len == 0 {
             let data_len = data.len();
             //crate::dbg!("smt set local not hit update data {}:\n", data_len);
             set_smt_data(node_buf, LEAF_NODE, key, data);
             unsafe {
-                self.set_unsafe(local_index, &node_buf[0..5 + data_len], true);
+                self.set_unsafe_in(
+                    domain::SMT_NODE,
+                    local_index,
+                    &node_buf[0..5 + data_len],
+                    Padding::Legacy,
+                );
             }
         } else {
             //crate::dbg!("smt set local hit:\n");
@@ -242,38 +479,56 @@ impl Merkle {
                     // if hit the current node
                     set_smt_data(node_buf, LEAF_NODE, key, data);
                     unsafe {
-                        self.set_unsafe(local_index, &node_buf[0..5 + data_len], true);
+                        self.set_unsafe_in(
+                            domain::SMT_NODE,
+                            local_index,
+                            &node_buf[0..5 + data_len],
+                            Padding::Legacy,
+                        );
                     }
                 } else {
                     //crate::dbg!("key not match, creating sub node:\n");
                     // conflict of key here
                     // 1. start a new merkle sub tree
-                    let mut sub_merkle = Merkle::new();
-                    sub_merkle.smt_set_local(
-                        &node_buf[1..5],
-                        path_index + 1,
-                        &node_buf[5..len as usize],
-                    );
+                    // the recursion reuses the node buffer, so move the old entry out of it
+                    let old_key: [u64; 4] = node_buf[1..5].try_into().unwrap();
+                    let old_data = node_buf[5..len as usize].to_vec();
+                    let mut sub_merkle = self.sub_tree(EMPTY_ROOTS[self.depth as usize]);
+                    sub_merkle.smt_set_local(&old_key, path_index + 1, &old_data);
                     sub_merkle.smt_set_local(key, path_index + 1, data);
+                    let node_buf = unsafe { &mut *std::ptr::addr_of_mut!(DATA_NODE_BUF) };
                     set_smt_data(node_buf, TREE_NODE, sub_merkle.root.as_slice(), &[]);
-                    // 2 update the current node with the sub merkle tree
-                    // OPT: shoulde be able to use the hint_hash in the future
-                    self.set(local_index, &node_buf[0..5], true, None);
+                    // 2 update the current node with the sub merkle tree, the leaf is
+                    // unchanged since it was read into hint_hash
+                    self.set_in(
+                        domain::SMT_NODE,
+                        local_index,
+                        &node_buf[0..5],
+                        Padding::Legacy,
+                        Some(&hint_hash),
+                    );
                 }
             } else {
                 //crate::dbg!("current node for set is node:\n");
                 // the node is already a sub merkle
-                unsafe { require((node_buf[0] & 0x1) == TREE_NODE) };
-                let mut sub_merkle = Merkle::load(node_buf[1..5].try_into().unwrap());
+                H::require((node_buf[0] & 0x1) == TREE_NODE);
+                let mut sub_merkle = self.sub_tree(node_buf[1..5].try_into().unwrap());
                 sub_merkle.smt_set_local(key, path_index + 1, data);
+                let node_buf = unsafe { &mut *std::ptr::addr_of_mut!(DATA_NODE_BUF) };
                 set_smt_data(node_buf, TREE_NODE, sub_merkle.root.as_slice(), &[]);
-                self.set(local_index, &node_buf[0..5], true, None);
+                self.set_in(
+                    domain::SMT_NODE,
+                    local_index,
+                    &node_buf[0..5],
+                    Padding::Legacy,
+                    Some(&hint_hash),
+                );
             }
         }
     }
 }
 
-impl SMT for Merkle {
+impl<H: Host> SMT for Merkle<H> {
     fn smt_get(&self, key: &[u64; 4], data: &mut [u64]) -> u64 {
         self.smt_get_local(key, 0, data)
     }
@@ -294,13 +549,13 @@ fn is_empty(a: u64) -> bool {
     (a & IS_EMPTY_BIT) == 0
 }
 
-impl Merkle {
+impl<H: Host> Merkle<H> {
     // optimized version for
     fn smt_get_local_u64(&self, key: u64, path_index: usize) -> u64 {
         //crate::dbg!("start smt_get_local {}\n", path_index);
-        unsafe { require(path_index < 2) };
+        H::require(path_index < 2);
         let local_index = (key >> (32 * (path_index % 2))) as u32;
-        // pad is true since the leaf might the root of a sub merkle
+        // legacy padding since the leaf might the root of a sub merkle
         let mut stored_data = [0; 4];
         self.get_simple(local_index, &mut stored_data);
         // data is stored in little endian
@@ -310,25 +565,23 @@ impl Merkle {
             let is_empty = is_empty(stored_data[3]);
             let stored_key = stored_data[0];
             if (!is_empty) && key == stored_key {
-                return stored_data[1];
+                stored_data[1]
             } else {
                 // is empty or not hit
-                return 0;
+                0
             }
         } else {
             //crate::dbg!("smt_get_local is node: continue in sub merkle\n");
             // make sure that there are only 2 level
-            unsafe {
-                crate::require(path_index == 0);
-            }
-            stored_data[3] = stored_data[3] & !IS_NODE_BIT;
-            let sub_merkle = Merkle::load(stored_data);
+            H::require(path_index == 0);
+            stored_data[3] &= !IS_NODE_BIT;
+            let sub_merkle = Self::load_with_host(stored_data);
             sub_merkle.smt_get_local_u64(key, path_index + 1)
         }
     }
 
     fn smt_set_local_u64(&mut self, key: u64, path_index: usize, data: u64) {
-        unsafe { require(path_index < 2) };
+        H::require(path_index < 2);
         let local_index = (key >> (32 * path_index)) as u32;
         let mut stored_data = [0; 4];
         self.get_simple(local_index, &mut stored_data);
@@ -337,24 +590,26 @@ impl Merkle {
         // LEAF_NODE must equal zero
         if is_leaf {
             let is_empty = is_empty(stored_data[3]);
+            // the leaf was just read, so the sets below that do not touch a sub tree in between
+            // are already paired
             if is_empty {
-                self.set_simple(local_index, &[key, data, 0, IS_EMPTY_BIT], None);
+                unsafe { self.set_simple_unsafe(local_index, &[key, data, 0, IS_EMPTY_BIT]) };
             } else {
                 //crate::dbg!("smt set local hit:\n");
                 if key == stored_data[0] {
                     //crate::dbg!("current node for set is leaf:\n");
                     stored_data[0] = key;
                     stored_data[1] = data;
-                    self.set_simple(local_index, &stored_data, None);
+                    unsafe { self.set_simple_unsafe(local_index, &stored_data) };
                 } else {
                     //crate::dbg!("key not match, creating sub node:\n");
                     // conflict of key here
                     // 1. start a new merkle sub tree
-                    let mut sub_merkle = Merkle::new();
+                    let mut sub_merkle = Self::new_with_host();
                     sub_merkle.smt_set_local_u64(stored_data[0], path_index + 1, stored_data[1]);
                     sub_merkle.smt_set_local_u64(key, path_index + 1, data);
                     stored_data = sub_merkle.root;
-                    stored_data[3] = stored_data[3] | IS_NODE_BIT;
+                    stored_data[3] |= IS_NODE_BIT;
                     // 2 update the current node with the sub merkle tree
                     self.set_simple(local_index, &stored_data, None);
                 }
@@ -362,24 +617,25 @@ impl Merkle {
         } else {
             //crate::dbg!("current node for set is node:\n");
             // make sure that there are only 2 level
-            unsafe {
-                crate::require(path_index == 0);
-            }
-            stored_data[3] = stored_data[3] & !IS_NODE_BIT;
-            let mut sub_merkle = Merkle::load(stored_data);
+            H::require(path_index == 0);
+            stored_data[3] &= !IS_NODE_BIT;
+            let mut sub_merkle = Self::load_with_host(stored_data);
             sub_merkle.smt_set_local_u64(key, path_index + 1, data);
-            sub_merkle.root[3] = sub_merkle.root[3] | IS_NODE_BIT;
+            sub_merkle.root[3] |= IS_NODE_BIT;
             self.set_simple(local_index, &sub_merkle.root, None);
         }
     }
 }
 
-impl SMTU64 for Merkle {
+/// The two levels of 32 bits of the u64 map need trees of the full depth
+impl<H: Host> SMTU64 for Merkle<H> {
     fn smt_get(&self, key: u64) -> u64 {
+        H::require(self.depth == MAX_DEPTH);
         self.smt_get_local_u64(key, 0)
     }
 
     fn smt_set(&mut self, key: u64, data: u64) {
+        H::require(self.depth == MAX_DEPTH);
         self.smt_set_local_u64(key, 0, data)
     }
 }
@@ -0,0 +1,390 @@
+//! Pure-Rust stand-in for the zkWasm host imports.
+//!
+//! With the `mock-host` feature enabled on a native target, every host function the SDK
+//! imports is provided by this module instead, so guest code can run under `cargo test`.
+//! The functions follow the same call sequences as the zkWasm host (four limbs per root,
+//! 32 limbs per poseidon round, 12 limbs per babyjubjub term, ...). Host state lives in a
+//! thread local, so every test thread gets its own host.
+
+// the host functions stay `unsafe` so that call sites written for the extern imports compile unchanged
+#![allow(clippy::missing_safety_doc)]
+
+#[cfg(target_arch = "wasm32")]
+compile_error!("the mock-host feature is only available on native targets");
+
+use crate::offchain::jubjub::Point;
+use crate::offchain::merkle::MerkleDb;
+use crate::offchain::poseidon::Poseidon;
+use crate::offchain::Fr;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Default)]
+struct PoseidonContext {
+    hasher: Option<Poseidon>,
+    limbs: Vec<u64>,
+    buf: Vec<Fr>,
+    result: [u64; 4],
+    cursor: usize,
+}
+
+struct BabyJubjubContext {
+    acc: Point,
+    limbs: Vec<u64>,
+    result: [u64; 8],
+    cursor: usize,
+}
+
+#[derive(Default)]
+struct CacheContext {
+    mode: u64,
+    hash: [u64; 4],
+    hash_cursor: usize,
+    data: Vec<u64>,
+    fetch: VecDeque<u64>,
+    store: HashMap<[u64; 4], Vec<u64>>,
+}
+
+#[derive(Default)]
+struct WitnessContext {
+    queue: VecDeque<u64>,
+    index: u64,
+    indexed: HashMap<u64, VecDeque<u64>>,
+}
+
+struct MockHost {
+    calls: u64,
+    public_inputs: VecDeque<u64>,
+    private_inputs: VecDeque<u64>,
+    outputs: Vec<u64>,
+    context_in: VecDeque<u64>,
+    context_out: Vec<u64>,
+    debug: String,
+    db: MerkleDb,
+    poseidon: PoseidonContext,
+    jubjub: BabyJubjubContext,
+    cache: CacheContext,
+    witness: WitnessContext,
+}
+
+impl MockHost {
+    fn new() -> Self {
+        MockHost {
+            calls: 0,
+            public_inputs: VecDeque::new(),
+            private_inputs: VecDeque::new(),
+            outputs: vec![],
+            context_in: VecDeque::new(),
+            context_out: vec![],
+            debug: String::new(),
+            db: MerkleDb::new(),
+            poseidon: PoseidonContext::default(),
+            jubjub: BabyJubjubContext {
+                acc: Point::identity(),
+                limbs: vec![],
+                result: [0; 8],
+                cursor: 0,
+            },
+            cache: CacheContext::default(),
+            witness: WitnessContext::default(),
+        }
+    }
+}
+
+thread_local! {
+    static HOST: RefCell<MockHost> = RefCell::new(MockHost::new());
+}
+
+fn with_host<R>(f: impl FnOnce(&mut MockHost) -> R) -> R {
+    HOST.with(|h| {
+        let mut host = h.borrow_mut();
+        host.calls += 1;
+        f(&mut host)
+    })
+}
+
+/// Drop all host state (inputs, outputs, merkle db, caches) of the current thread
+pub fn reset() {
+    HOST.with(|h| *h.borrow_mut() = MockHost::new());
+    crate::session::reset();
+}
+
+/// Queue the values returned by `wasm_input(1)`
+pub fn set_public_inputs(inputs: &[u64]) {
+    HOST.with(|h| h.borrow_mut().public_inputs = inputs.iter().copied().collect());
+}
+
+/// Queue the values returned by `wasm_input(0)`
+pub fn set_private_inputs(inputs: &[u64]) {
+    HOST.with(|h| h.borrow_mut().private_inputs = inputs.iter().copied().collect());
+}
+
+/// Queue the values returned by `wasm_read_context`
+pub fn set_context(context: &[u64]) {
+    HOST.with(|h| h.borrow_mut().context_in = context.iter().copied().collect());
+}
+
+/// Values emitted so far through `wasm_output`
+pub fn outputs() -> Vec<u64> {
+    HOST.with(|h| h.borrow().outputs.clone())
+}
+
+/// Values emitted so far through `wasm_write_context`
+pub fn written_context() -> Vec<u64> {
+    HOST.with(|h| h.borrow().context_out.clone())
+}
+
+/// Everything printed so far through `wasm_dbg` and `wasm_dbg_char`
+pub fn debug_output() -> String {
+    HOST.with(|h| h.borrow().debug.clone())
+}
+
+/// Root of the empty merkle tree, the root returned by `Merkle::new`
+pub fn empty_merkle_root() -> [u64; 4] {
+    HOST.with(|h| h.borrow().db.empty_root())
+}
+
+pub unsafe fn wasm_input(is_public: u32) -> u64 {
+    with_host(|h| {
+        let v = if is_public != 0 {
+            h.public_inputs.pop_front()
+        } else {
+            h.private_inputs.pop_front()
+        };
+        v.unwrap_or_else(|| panic!("wasm_input({}): no more inputs", is_public))
+    })
+}
+
+pub unsafe fn wasm_output(v: u64) {
+    with_host(|h| h.outputs.push(v))
+}
+
+pub unsafe fn wasm_read_context() -> u64 {
+    with_host(|h| {
+        h.context_in
+            .pop_front()
+            .expect("wasm_read_context: no more context")
+    })
+}
+
+pub unsafe fn wasm_write_context(v: u64) {
+    with_host(|h| h.context_out.push(v))
+}
+
+pub unsafe fn require(cond: bool) {
+    with_host(|_| ());
+    if !cond {
+        panic!("require failed");
+    }
+}
+
+pub unsafe fn wasm_dbg(v: u64) {
+    let s = format!("dbg: {}\n", v);
+    print!("{}", s);
+    with_host(|h| h.debug.push_str(&s))
+}
+
+pub unsafe fn wasm_dbg_char(v: u64) {
+    let c = char::from(v as u8);
+    print!("{}", c);
+    with_host(|h| h.debug.push(c))
+}
+
+pub unsafe fn wasm_trace_size() -> u64 {
+    with_host(|h| h.calls)
+}
+
+pub unsafe fn merkle_setroot(x: u64) {
+    with_host(|h| h.db.merkle_setroot(x))
+}
+
+pub unsafe fn merkle_setdepth(x: u64) {
+    with_host(|h| h.db.merkle_setdepth(x))
+}
+
+pub unsafe fn merkle_address(x: u64) {
+    with_host(|h| h.db.merkle_address(x))
+}
+
+pub unsafe fn merkle_set(x: u64) {
+    with_host(|h| h.db.merkle_set(x))
+}
+
+pub unsafe fn merkle_get() -> u64 {
+    with_host(|h| h.db.merkle_get())
+}
+
+pub unsafe fn merkle_getroot() -> u64 {
+    with_host(|h| h.db.merkle_getroot())
+}
+
+pub unsafe fn merkle_fetch_data() -> u64 {
+    with_host(|h| h.db.merkle_fetch_data())
+}
+
+pub unsafe fn merkle_put_data(x: u64) {
+    with_host(|h| h.db.merkle_put_data(x))
+}
+
+pub unsafe fn poseidon_new(x: u64) {
+    with_host(|h| {
+        let p = &mut h.poseidon;
+        p.limbs.clear();
+        p.buf.clear();
+        if x != 0 {
+            p.hasher = Some(Poseidon::hasher());
+        }
+    })
+}
+
+pub unsafe fn poseidon_push(x: u64) {
+    with_host(|h| {
+        let p = &mut h.poseidon;
+        p.limbs.push(x);
+        if p.limbs.len() == 4 {
+            p.buf.push(Fr::from_limbs(&[
+                p.limbs[0], p.limbs[1], p.limbs[2], p.limbs[3],
+            ]));
+            p.limbs.clear();
+        }
+    })
+}
+
+pub unsafe fn poseidon_finalize() -> u64 {
+    with_host(|h| {
+        let p = &mut h.poseidon;
+        if p.cursor == 0 {
+            assert!(
+                p.buf.len() == 8,
+                "poseidon_finalize: expected 32 pushed limbs, got {}",
+                p.buf.len() * 4 + p.limbs.len()
+            );
+            let hasher = p
+                .hasher
+                .as_mut()
+                .expect("poseidon_finalize: poseidon_new(1) was never called");
+            p.result = hasher.absorb(&p.buf).to_limbs();
+        }
+        let v = p.result[p.cursor];
+        p.cursor = (p.cursor + 1) % 4;
+        v
+    })
+}
+
+pub unsafe fn babyjubjub_sum_new(x: u64) {
+    with_host(|h| {
+        let j = &mut h.jubjub;
+        j.limbs.clear();
+        if x != 0 {
+            j.acc = Point::identity();
+        }
+    })
+}
+
+pub unsafe fn babyjubjub_sum_push(x: u64) {
+    with_host(|h| {
+        let j = &mut h.jubjub;
+        j.limbs.push(x);
+        if j.limbs.len() == 12 {
+            let l = &j.limbs;
+            let p = Point::from_limbs(&[l[0], l[1], l[2], l[3]], &[l[4], l[5], l[6], l[7]]);
+            j.acc = j.acc.add(&p.mul_scalar(&[l[8], l[9], l[10], l[11]]));
+            j.limbs.clear();
+        }
+    })
+}
+
+pub unsafe fn babyjubjub_sum_finalize() -> u64 {
+    with_host(|h| {
+        let j = &mut h.jubjub;
+        if j.cursor == 0 {
+            j.result[0..4].copy_from_slice(&j.acc.x.to_limbs());
+            j.result[4..8].copy_from_slice(&j.acc.y.to_limbs());
+        }
+        let v = j.result[j.cursor];
+        j.cursor = (j.cursor + 1) % 8;
+        v
+    })
+}
+
+pub unsafe fn cache_set_mode(x: u64) {
+    with_host(|h| {
+        let c = &mut h.cache;
+        c.mode = x;
+        c.hash_cursor = 0;
+        c.data.clear();
+        c.fetch.clear();
+    })
+}
+
+pub unsafe fn cache_set_hash(x: u64) {
+    with_host(|h| {
+        let c = &mut h.cache;
+        c.hash[c.hash_cursor] = x;
+        c.hash_cursor = (c.hash_cursor + 1) % 4;
+        if c.hash_cursor == 0 {
+            if c.mode == 1 {
+                c.store.insert(c.hash, std::mem::take(&mut c.data));
+            } else {
+                let data = c.store.get(&c.hash).cloned().unwrap_or_default();
+                c.fetch.push_back(data.len() as u64);
+                c.fetch.extend(data);
+            }
+        }
+    })
+}
+
+pub unsafe fn cache_store_data(x: u64) {
+    with_host(|h| h.cache.data.push(x))
+}
+
+pub unsafe fn cache_fetch_data() -> u64 {
+    with_host(|h| {
+        h.cache
+            .fetch
+            .pop_front()
+            .expect("cache_fetch_data: no pending fetch")
+    })
+}
+
+pub unsafe fn wasm_witness_insert(u: u64) {
+    with_host(|h| h.witness.queue.push_back(u))
+}
+
+pub unsafe fn wasm_witness_pop() -> u64 {
+    with_host(|h| {
+        h.witness
+            .queue
+            .pop_front()
+            .expect("wasm_witness_pop: witness queue is empty")
+    })
+}
+
+pub unsafe fn wasm_witness_set_index(x: u64) {
+    with_host(|h| h.witness.index = x)
+}
+
+pub unsafe fn wasm_witness_indexed_pop() -> u64 {
+    with_host(|h| {
+        let w = &mut h.witness;
+        w.indexed
+            .entry(w.index)
+            .or_default()
+            .pop_front()
+            .unwrap_or_else(|| panic!("wasm_witness_indexed_pop: queue {} is empty", w.index))
+    })
+}
+
+pub unsafe fn wasm_witness_indexed_insert(x: u64) {
+    with_host(|h| {
+        let w = &mut h.witness;
+        w.indexed.entry(w.index).or_default().push_back(x)
+    })
+}
+
+pub unsafe fn wasm_witness_indexed_push(x: u64) {
+    with_host(|h| {
+        let w = &mut h.witness;
+        w.indexed.entry(w.index).or_default().push_front(x)
+    })
+}
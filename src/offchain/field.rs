@@ -0,0 +1,206 @@
+use crate::jubjub::MODULUS;
+use primitive_types::U256;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// -MODULUS^{-1} mod 2^64
+const INV: u64 = 0xc2e1f593efffffff;
+
+/// 2^256 mod MODULUS
+const R: [u64; 4] = [
+    0xac96341c4ffffffb,
+    0x36fc76959f60cd29,
+    0x666ea36f7879462e,
+    0x0e0a77c19a07df2f,
+];
+
+/// 2^512 mod MODULUS
+const R2: [u64; 4] = [
+    0x1bb8e645ae216da7,
+    0x53fe3ab1e35c59e3,
+    0x8c49833d53bb8085,
+    0x0216d0b17f4e44a5,
+];
+
+/// Element of the BN254 scalar field, the native field of the zkWasm host circuits.
+///
+/// Values are kept in Montgomery form so that equality and hashing work on the
+/// canonical representation.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Fr([u64; 4]);
+
+fn geq(a: &[u64; 4], b: &[u64; 4]) -> bool {
+    for i in (0..4).rev() {
+        if a[i] != b[i] {
+            return a[i] > b[i];
+        }
+    }
+    true
+}
+
+fn sub_limbs(a: &[u64; 4], b: &[u64; 4]) -> [u64; 4] {
+    let mut r = [0; 4];
+    let mut borrow = false;
+    for i in 0..4 {
+        let (d, b1) = a[i].overflowing_sub(b[i]);
+        let (d, b2) = d.overflowing_sub(borrow as u64);
+        r[i] = d;
+        borrow = b1 || b2;
+    }
+    r
+}
+
+fn add_limbs(a: &[u64; 4], b: &[u64; 4]) -> [u64; 4] {
+    let mut r = [0; 4];
+    let mut carry = false;
+    for i in 0..4 {
+        let (s, c1) = a[i].overflowing_add(b[i]);
+        let (s, c2) = s.overflowing_add(carry as u64);
+        r[i] = s;
+        carry = c1 || c2;
+    }
+    r
+}
+
+fn mont_mul(a: &[u64; 4], b: &[u64; 4]) -> [u64; 4] {
+    let mut t = [0u64; 6];
+    for b_i in b {
+        let mut c = 0u128;
+        for j in 0..4 {
+            let v = t[j] as u128 + (a[j] as u128) * (*b_i as u128) + c;
+            t[j] = v as u64;
+            c = v >> 64;
+        }
+        let v = t[4] as u128 + c;
+        t[4] = v as u64;
+        t[5] = (v >> 64) as u64;
+
+        let m = t[0].wrapping_mul(INV);
+        let mut c = (t[0] as u128 + (m as u128) * (MODULUS[0] as u128)) >> 64;
+        for j in 1..4 {
+            let v = t[j] as u128 + (m as u128) * (MODULUS[j] as u128) + c;
+            t[j - 1] = v as u64;
+            c = v >> 64;
+        }
+        let v = t[4] as u128 + c;
+        t[3] = v as u64;
+        t[4] = t[5] + (v >> 64) as u64;
+    }
+    let r = [t[0], t[1], t[2], t[3]];
+    if t[4] != 0 || geq(&r, &MODULUS) {
+        sub_limbs(&r, &MODULUS)
+    } else {
+        r
+    }
+}
+
+impl Fr {
+    pub const ZERO: Fr = Fr([0; 4]);
+    pub const ONE: Fr = Fr(R);
+
+    /// Build a field element from little-endian u64 limbs, reducing modulo the field order
+    pub fn from_limbs(limbs: &[u64; 4]) -> Self {
+        let mut v = *limbs;
+        while geq(&v, &MODULUS) {
+            v = sub_limbs(&v, &MODULUS);
+        }
+        Fr(mont_mul(&v, &R2))
+    }
+
+    /// Canonical little-endian u64 limbs of the element
+    pub fn to_limbs(self) -> [u64; 4] {
+        mont_mul(&self.0, &[1, 0, 0, 0])
+    }
+
+    pub fn from_u64(v: u64) -> Self {
+        Self::from_limbs(&[v, 0, 0, 0])
+    }
+
+    pub fn from_u256(v: &U256) -> Self {
+        Self::from_limbs(&v.0)
+    }
+
+    pub fn to_u256(self) -> U256 {
+        U256(self.to_limbs())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    pub fn square(&self) -> Self {
+        *self * *self
+    }
+
+    /// Raise to the power of an exponent given in little-endian limbs
+    pub fn pow(&self, exp: &[u64; 4]) -> Self {
+        let mut r = Fr::ONE;
+        for i in (0..4).rev() {
+            for j in (0..64).rev() {
+                r = r.square();
+                if (exp[i] >> j) & 1 == 1 {
+                    r = r * *self;
+                }
+            }
+        }
+        r
+    }
+
+    /// Multiplicative inverse, `None` for zero
+    pub fn invert(&self) -> Option<Self> {
+        if self.is_zero() {
+            None
+        } else {
+            Some(self.pow(&sub_limbs(&MODULUS, &[2, 0, 0, 0])))
+        }
+    }
+}
+
+impl Add for Fr {
+    type Output = Fr;
+    fn add(self, rhs: Fr) -> Fr {
+        // both operands are below 2^254 so the sum never overflows 256 bits
+        let r = add_limbs(&self.0, &rhs.0);
+        if geq(&r, &MODULUS) {
+            Fr(sub_limbs(&r, &MODULUS))
+        } else {
+            Fr(r)
+        }
+    }
+}
+
+impl Sub for Fr {
+    type Output = Fr;
+    fn sub(self, rhs: Fr) -> Fr {
+        if geq(&self.0, &rhs.0) {
+            Fr(sub_limbs(&self.0, &rhs.0))
+        } else {
+            Fr(sub_limbs(&add_limbs(&self.0, &MODULUS), &rhs.0))
+        }
+    }
+}
+
+impl Mul for Fr {
+    type Output = Fr;
+    fn mul(self, rhs: Fr) -> Fr {
+        Fr(mont_mul(&self.0, &rhs.0))
+    }
+}
+
+impl Neg for Fr {
+    type Output = Fr;
+    fn neg(self) -> Fr {
+        Fr::ZERO - self
+    }
+}
+
+impl fmt::Debug for Fr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let l = self.to_limbs();
+        write!(
+            f,
+            "Fr(0x{:016x}{:016x}{:016x}{:016x})",
+            l[3], l[2], l[1], l[0]
+        )
+    }
+}
@@ -0,0 +1,72 @@
+use super::Fr;
+use crate::jubjub::BabyJubjubPoint;
+
+/// twisted edwards coefficient d of the curve -x^2 + y^2 = 1 + d x^2 y^2
+const D: [u64; 4] = [
+    0xd075ca8cf4d7eb8e,
+    0x039b2959ebb7c867,
+    0x3df072d799fd11fc,
+    0x1aee90f15f218969,
+];
+
+/// Affine point on the babyjubjub curve used by the host `babyjubjub_sum` op
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Point {
+    pub x: Fr,
+    pub y: Fr,
+}
+
+impl Point {
+    pub fn identity() -> Self {
+        Point {
+            x: Fr::ZERO,
+            y: Fr::ONE,
+        }
+    }
+
+    pub fn from_limbs(x: &[u64; 4], y: &[u64; 4]) -> Self {
+        Point {
+            x: Fr::from_limbs(x),
+            y: Fr::from_limbs(y),
+        }
+    }
+
+    pub fn add(&self, other: &Point) -> Point {
+        let t = Fr::from_limbs(&D) * self.x * other.x * self.y * other.y;
+        let x = (self.x * other.y + self.y * other.x) * (Fr::ONE + t).invert().unwrap();
+        let y = (self.y * other.y + self.x * other.x) * (Fr::ONE - t).invert().unwrap();
+        Point { x, y }
+    }
+
+    /// Multiply by a little-endian 256-bit scalar
+    pub fn mul_scalar(&self, scalar: &[u64; 4]) -> Point {
+        let mut r = Point::identity();
+        for i in (0..4).rev() {
+            for j in (0..64).rev() {
+                r = r.add(&r);
+                if (scalar[i] >> j) & 1 == 1 {
+                    r = r.add(self);
+                }
+            }
+        }
+        r
+    }
+}
+
+impl From<&BabyJubjubPoint> for Point {
+    fn from(p: &BabyJubjubPoint) -> Self {
+        Point {
+            x: Fr::from_u256(&p.x),
+            y: Fr::from_u256(&p.y),
+        }
+    }
+}
+
+impl From<Point> for BabyJubjubPoint {
+    fn from(p: Point) -> Self {
+        BabyJubjubPoint {
+            x: p.x.to_u256(),
+            y: p.y.to_u256(),
+        }
+    }
+}
@@ -0,0 +1,516 @@
+use super::poseidon::merkle_hash;
+use super::Fr;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Depth of the merkle tree behind the `merkle_*` host calls, unless set with `merkle_setdepth`
+pub const MERKLE_DEPTH: usize = 32;
+
+/// Hash of a leaf: the four limbs are split into two 128-bit field elements
+pub fn leaf_hash(data: &[u64; 4]) -> Fr {
+    merkle_hash(
+        &Fr::from_limbs(&[data[0], data[1], 0, 0]),
+        &Fr::from_limbs(&[data[2], data[3], 0, 0]),
+    )
+}
+
+#[derive(Debug)]
+pub enum MerkleDbError {
+    /// A node on the requested path is neither stored nor an empty subtree root
+    MissingNode {
+        hash: [u64; 4],
+        height: usize,
+    },
+    /// A leaf hash without stored leaf data
+    MissingLeaf {
+        hash: [u64; 4],
+    },
+    IndexOutOfRange(u64),
+    Io(io::Error),
+}
+
+impl fmt::Display for MerkleDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MerkleDbError::MissingNode { hash, height } => {
+                write!(f, "unknown merkle node {:?} at height {}", hash, height)
+            }
+            MerkleDbError::MissingLeaf { hash } => write!(f, "unknown merkle leaf {:?}", hash),
+            MerkleDbError::IndexOutOfRange(index) => {
+                write!(f, "merkle index {} out of range", index)
+            }
+            MerkleDbError::Io(e) => write!(f, "merkle db io error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MerkleDbError {}
+
+impl From<io::Error> for MerkleDbError {
+    fn from(e: io::Error) -> Self {
+        MerkleDbError::Io(e)
+    }
+}
+
+/// Backend of a `MerkleDb`. Nodes are addressed by their hash so nothing is ever overwritten.
+pub trait MerkleStore {
+    fn node(&self, hash: &Fr) -> Option<(Fr, Fr)>;
+    fn leaf(&self, hash: &Fr) -> Option<[u64; 4]>;
+    fn put_node(&mut self, hash: Fr, children: (Fr, Fr)) -> io::Result<()>;
+    fn put_leaf(&mut self, hash: Fr, data: [u64; 4]) -> io::Result<()>;
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    nodes: HashMap<Fr, (Fr, Fr)>,
+    leaves: HashMap<Fr, [u64; 4]>,
+}
+
+impl MerkleStore for MemoryStore {
+    fn node(&self, hash: &Fr) -> Option<(Fr, Fr)> {
+        self.nodes.get(hash).copied()
+    }
+    fn leaf(&self, hash: &Fr) -> Option<[u64; 4]> {
+        self.leaves.get(hash).copied()
+    }
+    fn put_node(&mut self, hash: Fr, children: (Fr, Fr)) -> io::Result<()> {
+        self.nodes.insert(hash, children);
+        Ok(())
+    }
+    fn put_leaf(&mut self, hash: Fr, data: [u64; 4]) -> io::Result<()> {
+        self.leaves.insert(hash, data);
+        Ok(())
+    }
+}
+
+const RECORD_NODE: u8 = 0;
+const RECORD_LEAF: u8 = 1;
+
+/// Append-only log of nodes and leaves, replayed into memory when opened.
+///
+/// Records are a tag byte followed by little-endian limbs: the hash, then either the two
+/// children (node) or the leaf data.
+pub struct FileStore {
+    file: File,
+    mem: MemoryStore,
+}
+
+fn read_limbs(buf: &[u8]) -> [u64; 4] {
+    let mut limbs = [0; 4];
+    for (l, c) in limbs.iter_mut().zip(buf.chunks(8)) {
+        *l = u64::from_le_bytes(c.try_into().unwrap());
+    }
+    limbs
+}
+
+fn write_limbs(buf: &mut Vec<u8>, limbs: &[u64; 4]) {
+    for l in limbs {
+        buf.extend_from_slice(&l.to_le_bytes());
+    }
+}
+
+impl FileStore {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+        let mut mem = MemoryStore::default();
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            let len = match rest[0] {
+                RECORD_NODE => 1 + 32 * 3,
+                RECORD_LEAF => 1 + 32 * 2,
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "bad record tag")),
+            };
+            if rest.len() < len {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "truncated merkle db record",
+                ));
+            }
+            let hash = Fr::from_limbs(&read_limbs(&rest[1..33]));
+            if rest[0] == RECORD_NODE {
+                let left = Fr::from_limbs(&read_limbs(&rest[33..65]));
+                let right = Fr::from_limbs(&read_limbs(&rest[65..97]));
+                mem.put_node(hash, (left, right))?;
+            } else {
+                mem.put_leaf(hash, read_limbs(&rest[33..65]))?;
+            }
+            rest = &rest[len..];
+        }
+        Ok(FileStore { file, mem })
+    }
+}
+
+impl MerkleStore for FileStore {
+    fn node(&self, hash: &Fr) -> Option<(Fr, Fr)> {
+        self.mem.node(hash)
+    }
+    fn leaf(&self, hash: &Fr) -> Option<[u64; 4]> {
+        self.mem.leaf(hash)
+    }
+    fn put_node(&mut self, hash: Fr, children: (Fr, Fr)) -> io::Result<()> {
+        if self.mem.node(&hash).is_some() {
+            return Ok(());
+        }
+        let mut buf = vec![RECORD_NODE];
+        write_limbs(&mut buf, &hash.to_limbs());
+        write_limbs(&mut buf, &children.0.to_limbs());
+        write_limbs(&mut buf, &children.1.to_limbs());
+        self.file.write_all(&buf)?;
+        self.mem.put_node(hash, children)
+    }
+    fn put_leaf(&mut self, hash: Fr, data: [u64; 4]) -> io::Result<()> {
+        if self.mem.leaf(&hash).is_some() {
+            return Ok(());
+        }
+        let mut buf = vec![RECORD_LEAF];
+        write_limbs(&mut buf, &hash.to_limbs());
+        write_limbs(&mut buf, &data);
+        self.file.write_all(&buf)?;
+        self.mem.put_leaf(hash, data)
+    }
+}
+
+/// Authentication path of a leaf, siblings are ordered from the leaf up to the root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthPath {
+    pub index: u64,
+    pub leaf: [u64; 4],
+    pub siblings: Vec<[u64; 4]>,
+}
+
+impl AuthPath {
+    /// Root obtained by hashing the leaf up along the siblings
+    pub fn compute_root(&self) -> [u64; 4] {
+        let mut node = leaf_hash(&self.leaf);
+        for (height, sibling) in self.siblings.iter().enumerate() {
+            let sibling = Fr::from_limbs(sibling);
+            node = if (self.index >> height) & 1 == 0 {
+                merkle_hash(&node, &sibling)
+            } else {
+                merkle_hash(&sibling, &node)
+            };
+        }
+        node.to_limbs()
+    }
+
+    pub fn verify(&self, root: &[u64; 4]) -> bool {
+        self.compute_root() == *root
+    }
+}
+
+/// Cursor state of the `merkle_*` call protocol
+#[derive(Default)]
+struct Session {
+    /// depth requested by `merkle_setdepth` for the next access
+    next_depth: Option<usize>,
+    depth: usize,
+    address: u64,
+    root: [u64; 4],
+    root_buf: [u64; 4],
+    root_cursor: usize,
+    getroot_cursor: usize,
+    get_data: [u64; 4],
+    get_cursor: usize,
+    set_buf: [u64; 4],
+    set_cursor: usize,
+    /// data of `merkle_put_data` for the leaf of the next set
+    put_data: Vec<u64>,
+    /// length and data `merkle_fetch_data` still returns for the leaf of the last get
+    fetch_data: VecDeque<u64>,
+}
+
+/// Off-chain copy of the poseidon merkle trees the host maintains, 32 levels deep by default.
+///
+/// Every root ever produced stays readable. Besides direct `get_leaf`/`set_leaf` access the db
+/// answers the same `merkle_*` call sequences as the host, leaf data included, so it can be
+/// driven with a recorded guest session to reproduce the roots before proving.
+pub struct MerkleDb<S: MerkleStore = MemoryStore> {
+    store: S,
+    /// `defaults[h]` is the root of an empty subtree of height `h`
+    defaults: Vec<Fr>,
+    session: Session,
+    /// leaf data of `merkle_put_data` by leaf value, kept in memory only
+    data: HashMap<[u64; 4], Vec<u64>>,
+}
+
+impl Default for MerkleDb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MerkleDb {
+    /// In-memory db
+    pub fn new() -> Self {
+        Self::with_store(MemoryStore::default())
+    }
+}
+
+impl MerkleDb<FileStore> {
+    /// File backed db, creating the file if it does not exist
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::with_store(FileStore::open(path)?))
+    }
+}
+
+impl<S: MerkleStore> MerkleDb<S> {
+    pub fn with_store(store: S) -> Self {
+        let mut defaults = vec![leaf_hash(&[0; 4])];
+        for h in 0..MERKLE_DEPTH {
+            defaults.push(merkle_hash(&defaults[h], &defaults[h]));
+        }
+        let session = Session {
+            root: defaults[MERKLE_DEPTH].to_limbs(),
+            depth: MERKLE_DEPTH,
+            ..Session::default()
+        };
+        MerkleDb {
+            store,
+            defaults,
+            session,
+            data: HashMap::new(),
+        }
+    }
+
+    /// Root of the tree with every leaf set to zero
+    pub fn empty_root(&self) -> [u64; 4] {
+        self.empty_root_at(MERKLE_DEPTH)
+    }
+
+    /// Root of the tree of depth `depth` with every leaf set to zero
+    pub fn empty_root_at(&self, depth: usize) -> [u64; 4] {
+        self.defaults[depth].to_limbs()
+    }
+
+    /// Whether `root` is a root this db can serve
+    pub fn contains_root(&self, root: &[u64; 4]) -> bool {
+        let root = Fr::from_limbs(root);
+        self.defaults.contains(&root) || self.store.node(&root).is_some()
+    }
+
+    fn children(&self, node: &Fr, height: usize) -> Result<(Fr, Fr), MerkleDbError> {
+        match self.store.node(node) {
+            Some(c) => Ok(c),
+            None if *node == self.defaults[height] => {
+                Ok((self.defaults[height - 1], self.defaults[height - 1]))
+            }
+            None => Err(MerkleDbError::MissingNode {
+                hash: node.to_limbs(),
+                height,
+            }),
+        }
+    }
+
+    /// Leaf hash and the siblings of the path of `index`, from the leaf up
+    fn path(
+        &self,
+        root: &[u64; 4],
+        index: u64,
+        depth: usize,
+    ) -> Result<(Fr, Vec<Fr>), MerkleDbError> {
+        if depth > MERKLE_DEPTH || index >= (1u64 << depth) {
+            return Err(MerkleDbError::IndexOutOfRange(index));
+        }
+        let mut node = Fr::from_limbs(root);
+        let mut siblings = vec![];
+        for height in (1..=depth).rev() {
+            let (left, right) = self.children(&node, height)?;
+            if (index >> (height - 1)) & 1 == 0 {
+                siblings.push(right);
+                node = left;
+            } else {
+                siblings.push(left);
+                node = right;
+            }
+        }
+        siblings.reverse();
+        Ok((node, siblings))
+    }
+
+    fn leaf_data(&self, leaf: &Fr) -> Result<[u64; 4], MerkleDbError> {
+        match self.store.leaf(leaf) {
+            Some(data) => Ok(data),
+            None if *leaf == self.defaults[0] => Ok([0; 4]),
+            None => Err(MerkleDbError::MissingLeaf {
+                hash: leaf.to_limbs(),
+            }),
+        }
+    }
+
+    pub fn get_leaf(&self, root: &[u64; 4], index: u64) -> Result<[u64; 4], MerkleDbError> {
+        self.get_leaf_at(root, index, MERKLE_DEPTH)
+    }
+
+    /// Same as `get_leaf` in a tree of depth `depth`
+    pub fn get_leaf_at(
+        &self,
+        root: &[u64; 4],
+        index: u64,
+        depth: usize,
+    ) -> Result<[u64; 4], MerkleDbError> {
+        let (leaf, _) = self.path(root, index, depth)?;
+        self.leaf_data(&leaf)
+    }
+
+    /// Set a leaf under `root` and return the new root
+    pub fn set_leaf(
+        &mut self,
+        root: &[u64; 4],
+        index: u64,
+        data: &[u64; 4],
+    ) -> Result<[u64; 4], MerkleDbError> {
+        self.set_leaf_at(root, index, MERKLE_DEPTH, data)
+    }
+
+    /// Same as `set_leaf` in a tree of depth `depth`
+    pub fn set_leaf_at(
+        &mut self,
+        root: &[u64; 4],
+        index: u64,
+        depth: usize,
+        data: &[u64; 4],
+    ) -> Result<[u64; 4], MerkleDbError> {
+        let (_, siblings) = self.path(root, index, depth)?;
+        let mut node = leaf_hash(data);
+        self.store.put_leaf(node, *data)?;
+        for (height, sibling) in siblings.iter().enumerate() {
+            let pair = if (index >> height) & 1 == 0 {
+                (node, *sibling)
+            } else {
+                (*sibling, node)
+            };
+            node = merkle_hash(&pair.0, &pair.1);
+            self.store.put_node(node, pair)?;
+        }
+        Ok(node.to_limbs())
+    }
+
+    /// Authentication path of leaf `index` in the tree with root `root`
+    pub fn auth_path(&self, root: &[u64; 4], index: u64) -> Result<AuthPath, MerkleDbError> {
+        self.auth_path_at(root, index, MERKLE_DEPTH)
+    }
+
+    /// Same as `auth_path` in a tree of depth `depth`
+    pub fn auth_path_at(
+        &self,
+        root: &[u64; 4],
+        index: u64,
+        depth: usize,
+    ) -> Result<AuthPath, MerkleDbError> {
+        let (leaf, siblings) = self.path(root, index, depth)?;
+        Ok(AuthPath {
+            index,
+            leaf: self.leaf_data(&leaf)?,
+            siblings: siblings.iter().map(|s| s.to_limbs()).collect(),
+        })
+    }
+
+    /// Root the protocol session currently points at
+    pub fn session_root(&self) -> [u64; 4] {
+        self.session.root
+    }
+
+    /// Leaf index the protocol session currently points at
+    pub fn session_address(&self) -> u64 {
+        self.session.address
+    }
+
+    /// Depth of the tree of the current protocol session
+    pub fn session_depth(&self) -> usize {
+        self.session.depth
+    }
+
+    // The following follow the host semantics of the imports with the same names and panic
+    // where the host would fail.
+
+    pub fn merkle_setroot(&mut self, x: u64) {
+        let s = &mut self.session;
+        s.root_buf[s.root_cursor] = x;
+        s.root_cursor = (s.root_cursor + 1) % 4;
+        if s.root_cursor == 0 {
+            s.root = s.root_buf;
+        }
+    }
+
+    /// Depth of the tree of the next access, which is `MERKLE_DEPTH` otherwise
+    pub fn merkle_setdepth(&mut self, x: u64) {
+        assert!(
+            x <= MERKLE_DEPTH as u64,
+            "merkle_setdepth: depth {} not supported",
+            x
+        );
+        self.session.next_depth = Some(x as usize);
+    }
+
+    pub fn merkle_address(&mut self, x: u64) {
+        let depth = self.session.next_depth.take().unwrap_or(MERKLE_DEPTH);
+        assert!(
+            x < (1u64 << depth),
+            "merkle_address: index {} out of range of depth {}",
+            x,
+            depth
+        );
+        self.session.address = x;
+        self.session.depth = depth;
+    }
+
+    pub fn merkle_get(&mut self) -> u64 {
+        if self.session.get_cursor == 0 {
+            self.session.fetch_data.clear();
+            let s = &self.session;
+            let data = self.get_leaf_at(&s.root, s.address, s.depth);
+            self.session.get_data = data.unwrap_or_else(|e| panic!("merkle_get: {}", e));
+        }
+        let s = &mut self.session;
+        let v = s.get_data[s.get_cursor];
+        s.get_cursor = (s.get_cursor + 1) % 4;
+        v
+    }
+
+    pub fn merkle_set(&mut self, x: u64) {
+        let s = &mut self.session;
+        s.set_buf[s.set_cursor] = x;
+        s.set_cursor = (s.set_cursor + 1) % 4;
+        if s.set_cursor == 0 {
+            let (root, address, depth, data) = (s.root, s.address, s.depth, s.set_buf);
+            let root = self.set_leaf_at(&root, address, depth, &data);
+            self.session.root = root.unwrap_or_else(|e| panic!("merkle_set: {}", e));
+            if !self.session.put_data.is_empty() {
+                let put_data = std::mem::take(&mut self.session.put_data);
+                self.data.insert(data, put_data);
+            }
+        }
+    }
+
+    pub fn merkle_getroot(&mut self) -> u64 {
+        let s = &mut self.session;
+        let v = s.root[s.getroot_cursor];
+        s.getroot_cursor = (s.getroot_cursor + 1) % 4;
+        v
+    }
+
+    pub fn merkle_put_data(&mut self, x: u64) {
+        self.session.put_data.push(x);
+    }
+
+    pub fn merkle_fetch_data(&mut self) -> u64 {
+        if self.session.fetch_data.is_empty() {
+            let s = &self.session;
+            let leaf = self.get_leaf_at(&s.root, s.address, s.depth);
+            let leaf = leaf.unwrap_or_else(|e| panic!("merkle_fetch_data: {}", e));
+            let data = self.data.get(&leaf).cloned().unwrap_or_default();
+            self.session.fetch_data.push_back(data.len() as u64);
+            self.session.fetch_data.extend(data);
+        }
+        self.session.fetch_data.pop_front().unwrap()
+    }
+}
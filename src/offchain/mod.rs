@@ -0,0 +1,19 @@
+//! Native implementations of the primitives behind the zkWasm host circuits.
+//!
+//! Enabled by the `offchain` feature. Services that run next to a guest use these to compute
+//! exactly the same poseidon hashes, merkle roots and curve points the guest obtains from the
+//! host, e.g. to precompute leaf hashes and cache keys.
+
+mod field;
+pub mod jubjub;
+pub mod merkle;
+pub mod poseidon;
+pub mod proof;
+pub mod transcript;
+
+pub use field::Fr;
+
+/// Digest `OutputWriter::finish` emits in commit mode for the values written to it
+pub fn output_digest(values: &[u64]) -> [u64; 4] {
+    poseidon::PoseidonHasher::hash(values, crate::poseidon::Padding::LengthPrefixed)
+}
@@ -0,0 +1,289 @@
+use super::Fr;
+use crate::poseidon::Padding;
+use std::rc::Rc;
+
+/// Round constants and MDS matrix of a Poseidon instance over Fr with x^5 sbox
+pub struct Spec {
+    pub t: usize,
+    pub r_f: usize,
+    pub r_p: usize,
+    constants: Vec<Vec<Fr>>,
+    mds: Vec<Vec<Fr>>,
+}
+
+/// Grain LFSR used by the Poseidon reference implementation to derive its parameters
+struct Grain {
+    state: Vec<bool>,
+}
+
+impl Grain {
+    fn new(t: usize, r_f: usize, r_p: usize) -> Self {
+        let mut state = Vec::with_capacity(80);
+        let mut append = |len: usize, v: u64| {
+            for i in (0..len).rev() {
+                state.push((v >> i) & 1 == 1);
+            }
+        };
+        append(2, 1); // prime field
+        append(4, 0); // x^alpha sbox
+        append(12, 254); // field size in bits
+        append(12, t as u64);
+        append(10, r_f as u64);
+        append(10, r_p as u64);
+        append(30, (1 << 30) - 1);
+        let mut grain = Grain { state };
+        for _ in 0..160 {
+            grain.raw_bit();
+        }
+        grain
+    }
+
+    fn raw_bit(&mut self) -> bool {
+        let s = &self.state;
+        let b = s[62] ^ s[51] ^ s[38] ^ s[23] ^ s[13] ^ s[0];
+        self.state.remove(0);
+        self.state.push(b);
+        b
+    }
+
+    fn next_bit(&mut self) -> bool {
+        loop {
+            let keep = self.raw_bit();
+            let b = self.raw_bit();
+            if keep {
+                return b;
+            }
+        }
+    }
+
+    /// 254 bits interpreted in msb order
+    fn next_bits(&mut self) -> [u64; 4] {
+        let mut v = [0u64; 4];
+        for i in (0..254).rev() {
+            if self.next_bit() {
+                v[i / 64] |= 1 << (i % 64);
+            }
+        }
+        v
+    }
+
+    fn next_field_element(&mut self) -> Fr {
+        loop {
+            let v = self.next_bits();
+            let f = Fr::from_limbs(&v);
+            if f.to_limbs() == v {
+                return f;
+            }
+        }
+    }
+
+    fn next_field_element_without_rejection(&mut self) -> Fr {
+        Fr::from_limbs(&self.next_bits())
+    }
+}
+
+impl Spec {
+    pub fn new(t: usize, r_f: usize, r_p: usize) -> Self {
+        let mut grain = Grain::new(t, r_f, r_p);
+        let constants = (0..r_f + r_p)
+            .map(|_| (0..t).map(|_| grain.next_field_element()).collect())
+            .collect();
+        let xs: Vec<Fr> = (0..t)
+            .map(|_| grain.next_field_element_without_rejection())
+            .collect();
+        let ys: Vec<Fr> = (0..t)
+            .map(|_| grain.next_field_element_without_rejection())
+            .collect();
+        let mds = xs
+            .iter()
+            .map(|x| ys.iter().map(|y| (*x + *y).invert().unwrap()).collect())
+            .collect();
+        Spec {
+            t,
+            r_f,
+            r_p,
+            constants,
+            mds,
+        }
+    }
+
+    fn sbox(x: Fr) -> Fr {
+        let x2 = x.square();
+        x2.square() * x
+    }
+
+    fn permute(&self, state: &mut [Fr]) {
+        let half = self.r_f / 2;
+        for r in 0..self.r_f + self.r_p {
+            for (s, c) in state.iter_mut().zip(self.constants[r].iter()) {
+                *s = *s + *c;
+            }
+            if r < half || r >= half + self.r_p {
+                for s in state.iter_mut() {
+                    *s = Self::sbox(*s);
+                }
+            } else {
+                state[0] = Self::sbox(state[0]);
+            }
+            let mixed: Vec<Fr> = self
+                .mds
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .zip(state.iter())
+                        .fold(Fr::ZERO, |acc, (m, s)| acc + *m * *s)
+                })
+                .collect();
+            state.copy_from_slice(&mixed);
+        }
+    }
+}
+
+thread_local! {
+    /// spec of the hasher behind poseidon_new/poseidon_push/poseidon_finalize
+    static HASHER_SPEC: Rc<Spec> = Rc::new(Spec::new(9, 8, 63));
+    /// spec used for merkle leaves and nodes
+    static MERKLE_SPEC: Rc<Spec> = Rc::new(Spec::new(3, 8, 57));
+}
+
+/// Sponge state matching the host poseidon hasher: every absorbed block of `t - 1`
+/// elements costs one permutation and the digest is read from the first rate element.
+pub struct Poseidon {
+    spec: Rc<Spec>,
+    state: Vec<Fr>,
+}
+
+impl Poseidon {
+    fn with_spec(spec: Rc<Spec>) -> Self {
+        let mut state = vec![Fr::ZERO; spec.t];
+        state[0] = Fr::from_limbs(&[0, 1, 0, 0]); // 2^64
+        Poseidon { spec, state }
+    }
+
+    /// The 9-wide hasher used by `PoseidonHasher`
+    pub fn hasher() -> Self {
+        Self::with_spec(HASHER_SPEC.with(|s| s.clone()))
+    }
+
+    /// The 3-wide hasher used by the merkle tree
+    pub fn merkle() -> Self {
+        Self::with_spec(MERKLE_SPEC.with(|s| s.clone()))
+    }
+
+    pub fn rate(&self) -> usize {
+        self.spec.t - 1
+    }
+
+    /// Absorb one full block of `rate()` elements and return the current digest
+    pub fn absorb(&mut self, block: &[Fr]) -> Fr {
+        assert!(block.len() == self.rate());
+        for (s, v) in self.state[1..].iter_mut().zip(block.iter()) {
+            *s = *s + *v;
+        }
+        self.spec.permute(&mut self.state);
+        self.state[1]
+    }
+}
+
+/// Hash of a merkle node (or of a leaf split into two 128-bit halves)
+pub fn merkle_hash(left: &Fr, right: &Fr) -> Fr {
+    Poseidon::merkle().absorb(&[*left, *right])
+}
+
+/// Native twin of `crate::PoseidonHasher`.
+///
+/// Mirrors the guest side protocol limb for limb: limbs are packed four at a time into field
+/// elements, every 32 limbs form one absorbed round, and `finalize` pads the last group of four
+/// with zeros and appends the `1` marker before the final round.
+pub struct PoseidonHasher {
+    sponge: Poseidon,
+    limbs: Vec<u64>,
+}
+
+impl Default for PoseidonHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PoseidonHasher {
+    pub fn new() -> Self {
+        PoseidonHasher {
+            sponge: Poseidon::hasher(),
+            limbs: Vec::with_capacity(32),
+        }
+    }
+
+    /// Same as `crate::PoseidonHasher::hash`, in every padding mode
+    pub fn hash(data: &[u64], padding: Padding) -> [u64; 4] {
+        let mut hasher = Self::new();
+        hasher.update_padded(data, padding);
+        hasher.finalize()
+    }
+
+    /// Same as `crate::PoseidonHasher::with_domain`
+    pub fn with_domain(tag: u64) -> Self {
+        let mut hasher = Self::new();
+        hasher.update(tag);
+        hasher.update(0);
+        hasher.update(0);
+        hasher.update(0);
+        hasher
+    }
+
+    /// Same as `crate::PoseidonHasher::hash_with_domain`
+    pub fn hash_with_domain(tag: u64, data: &[u64], padding: Padding) -> [u64; 4] {
+        let mut hasher = Self::with_domain(tag);
+        hasher.update_padded(data, padding);
+        hasher.finalize()
+    }
+
+    fn update_padded(&mut self, data: &[u64], padding: Padding) {
+        padding.pad(data, |v| self.update(v));
+    }
+
+    /// Same as `crate::PoseidonHasher::hash_bytes`
+    pub fn hash_bytes(bytes: &[u8]) -> [u64; 4] {
+        let mut hasher = Self::new();
+        hasher.update_bytes(bytes);
+        hasher.finalize()
+    }
+
+    fn absorb_round(&mut self) -> [u64; 4] {
+        let block: Vec<Fr> = self
+            .limbs
+            .chunks(4)
+            .map(|c| Fr::from_limbs(&[c[0], c[1], c[2], c[3]]))
+            .collect();
+        self.limbs.clear();
+        self.sponge.absorb(&block).to_limbs()
+    }
+
+    pub fn update(&mut self, v: u64) {
+        self.limbs.push(v);
+        if self.limbs.len() == 32 {
+            self.absorb_round();
+        }
+    }
+
+    pub fn update_bytes(&mut self, bytes: &[u8]) {
+        while self.limbs.len() & 0x3 != 0 {
+            self.update(0);
+        }
+        for v in crate::poseidon::pack_bytes(bytes) {
+            self.update(v);
+        }
+    }
+
+    pub fn finalize(&mut self) -> [u64; 4] {
+        while self.limbs.len() & 0x3 != 0 {
+            self.limbs.push(0);
+        }
+        if self.limbs.len() == 32 {
+            self.absorb_round();
+        }
+        self.limbs.push(1);
+        self.limbs.resize(32, 0);
+        self.absorb_round()
+    }
+}
@@ -0,0 +1,99 @@
+//! Native prover side of `crate::MerkleProof`.
+//!
+//! `PoseidonTree` is a tree of its own, separate from `super::merkle::MerkleDb`: its roots are
+//! not host merkle roots, and `MerkleDb` paths can not be turned into a `MerkleProof`.
+
+use super::poseidon::PoseidonHasher;
+use crate::poseidon::{domain, Padding};
+use crate::proof::MerkleProof;
+use std::collections::HashMap;
+
+/// Tree node of a leaf value, same as `crate::proof::leaf_hash`
+pub fn leaf_hash(leaf: &[u64; 4]) -> [u64; 4] {
+    PoseidonHasher::hash_with_domain(domain::PROOF_LEAF, leaf, Padding::None)
+}
+
+/// Parent of two nodes, same as `crate::proof::node_hash`
+pub fn node_hash(left: &[u64; 4], right: &[u64; 4]) -> [u64; 4] {
+    let mut data = left.to_vec();
+    data.extend_from_slice(right);
+    PoseidonHasher::hash_with_domain(domain::PROOF_NODE, &data, Padding::None)
+}
+
+/// Sparse tree of the `MerkleProof` scheme, every leaf not set is `[0; 4]`
+pub struct PoseidonTree {
+    depth: usize,
+    /// leaf values that were set, by index
+    leaves: HashMap<u64, [u64; 4]>,
+    /// non default nodes by (height, index), height 0 being the hashed leaves
+    nodes: HashMap<(usize, u64), [u64; 4]>,
+    /// `defaults[h]` is the root of an empty subtree of height `h`
+    defaults: Vec<[u64; 4]>,
+}
+
+impl PoseidonTree {
+    pub fn new(depth: usize) -> Self {
+        assert!(depth < 64, "depth {} not supported", depth);
+        let mut defaults = vec![leaf_hash(&[0; 4])];
+        for h in 0..depth {
+            defaults.push(node_hash(&defaults[h], &defaults[h]));
+        }
+        PoseidonTree {
+            depth,
+            leaves: HashMap::new(),
+            nodes: HashMap::new(),
+            defaults,
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn node(&self, height: usize, index: u64) -> [u64; 4] {
+        self.nodes
+            .get(&(height, index))
+            .copied()
+            .unwrap_or(self.defaults[height])
+    }
+
+    pub fn root(&self) -> [u64; 4] {
+        self.node(self.depth, 0)
+    }
+
+    /// Leaf value at `index`
+    pub fn leaf(&self, index: u64) -> [u64; 4] {
+        self.leaves.get(&index).copied().unwrap_or([0; 4])
+    }
+
+    /// Set the leaf value at `index` and return the new root
+    pub fn set(&mut self, index: u64, leaf: [u64; 4]) -> [u64; 4] {
+        assert!(index >> self.depth == 0, "index {} out of range", index);
+        let mut node = leaf_hash(&leaf);
+        let mut index = index;
+        self.leaves.insert(index, leaf);
+        self.nodes.insert((0, index), node);
+        for height in 0..self.depth {
+            let sibling = self.node(height, index ^ 1);
+            node = if index & 1 == 0 {
+                node_hash(&node, &sibling)
+            } else {
+                node_hash(&sibling, &node)
+            };
+            index >>= 1;
+            self.nodes.insert((height + 1, index), node);
+        }
+        node
+    }
+
+    pub fn proof(&self, index: u64) -> MerkleProof {
+        assert!(index >> self.depth == 0, "index {} out of range", index);
+        MerkleProof {
+            leaf: self.leaf(index),
+            index,
+            siblings: (0..self.depth)
+                .map(|height| self.node(height, (index >> height) ^ 1))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use super::poseidon::PoseidonHasher;
+use crate::jubjub::BabyJubjubPoint;
+use crate::poseidon::{domain, Padding};
+use crate::transcript::reduce;
+
+/// Native twin of `crate::Transcript`, deriving the same challenges from the same values
+pub struct Transcript {
+    state: [u64; 4],
+    pending: Vec<u64>,
+}
+
+impl Transcript {
+    pub fn new(label: u64) -> Self {
+        Transcript {
+            state: [0; 4],
+            pending: vec![label],
+        }
+    }
+
+    pub fn append_u64(&mut self, v: u64) {
+        self.pending.push(v);
+    }
+
+    pub fn append_hash(&mut self, hash: &[u64; 4]) {
+        self.pending.extend_from_slice(hash);
+    }
+
+    pub fn append_point(&mut self, p: &BabyJubjubPoint) {
+        self.pending.extend_from_slice(&p.x.0);
+        self.pending.extend_from_slice(&p.y.0);
+    }
+
+    pub fn challenge_scalar(&mut self) -> [u64; 4] {
+        let mut input = self.state.to_vec();
+        input.append(&mut self.pending);
+        self.state =
+            PoseidonHasher::hash_with_domain(domain::TRANSCRIPT, &input, Padding::LengthPrefixed);
+        reduce(&self.state)
+    }
+}
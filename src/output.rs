@@ -0,0 +1,100 @@
+use crate::host::{Host, ZkWasmHost};
+use crate::jubjub::BabyJubjubPoint;
+use crate::poseidon::{Padding, PoseidonHasher};
+use primitive_types::U256;
+use std::marker::PhantomData;
+
+/// Builder for the public outputs of a program.
+///
+/// In direct mode every value is emitted through `wasm_output` right away. In commit mode the
+/// values are only absorbed into a poseidon hash and `finish` emits the 4 limb digest, so the
+/// instance verified on-chain stays four limbs long whatever the number of values. The hash is
+/// length prefixed and keeps every field element below the modulus, so the digest binds the
+/// exact sequence of values. Verifiers recompute it from the raw values with
+/// `offchain::output_digest`.
+///
+/// The host poseidon hasher is shared with `Merkle` and friends, so commit mode buffers the
+/// values and hashes them in one go at `finish`.
+pub struct OutputWriter<H: Host = ZkWasmHost> {
+    committed: Option<Vec<u64>>,
+    host: PhantomData<H>,
+}
+
+impl OutputWriter {
+    pub fn direct() -> Self {
+        Self::direct_with_host()
+    }
+
+    pub fn commit() -> Self {
+        Self::commit_with_host()
+    }
+}
+
+impl<H: Host> OutputWriter<H> {
+    pub fn direct_with_host() -> Self {
+        OutputWriter {
+            committed: None,
+            host: PhantomData,
+        }
+    }
+
+    pub fn commit_with_host() -> Self {
+        OutputWriter {
+            committed: Some(vec![]),
+            host: PhantomData,
+        }
+    }
+
+    pub fn is_commit(&self) -> bool {
+        self.committed.is_some()
+    }
+
+    pub fn write_u64(&mut self, v: u64) {
+        match &mut self.committed {
+            Some(values) => values.push(v),
+            None => H::wasm_output(v),
+        }
+    }
+
+    pub fn write_i64(&mut self, v: i64) {
+        self.write_u64(v as u64)
+    }
+
+    pub fn write_u32(&mut self, v: u32) {
+        self.write_u64(v as u64)
+    }
+
+    pub fn write_bool(&mut self, v: bool) {
+        self.write_u64(v as u64)
+    }
+
+    pub fn write_slice(&mut self, data: &[u64]) {
+        for d in data {
+            self.write_u64(*d);
+        }
+    }
+
+    pub fn write_limbs(&mut self, v: &[u64; 4]) {
+        self.write_slice(v)
+    }
+
+    pub fn write_u256(&mut self, v: &U256) {
+        self.write_slice(&v.0)
+    }
+
+    pub fn write_point(&mut self, p: &BabyJubjubPoint) {
+        self.write_u256(&p.x);
+        self.write_u256(&p.y);
+    }
+
+    /// Emit the digest in commit mode and return it, nothing to do in direct mode
+    pub fn finish(self) -> Option<[u64; 4]> {
+        self.committed.map(|values| {
+            let digest = PoseidonHasher::<H>::hash_with_host(&values, Padding::LengthPrefixed);
+            for d in digest {
+                H::wasm_output(d);
+            }
+            digest
+        })
+    }
+}
@@ -0,0 +1,50 @@
+//! Panic reporting for guests.
+//!
+//! By default a panic in a guest ends in an `unreachable` trap without any hint of what went
+//! wrong. `install` sets a panic hook that writes `panicked at <file>:<line>:<column>: <message>`
+//! through `wasm_dbg_str` and then fails through `require(false)`. `handle_panic` reports the
+//! panic info and fails the same way, with the signature of a `#[panic_handler]` for guests that
+//! provide their own handler.
+//!
+//! The `no-panic-hook` feature strips the reporting for release builds: `install` leaves the
+//! current hook in place and `handle_panic` only fails through `require(false)`.
+
+use std::any::Any;
+use std::panic::Location;
+
+/// Location and message of a panic, from the payload and location of its info
+pub fn panic_message(payload: &(dyn Any + Send), location: Option<&Location>) -> String {
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "Box<dyn Any>"
+    };
+    match location {
+        Some(l) => format!("{}:{}:{}: {}", l.file(), l.line(), l.column(), message),
+        None => message.to_string(),
+    }
+}
+
+/// Install the reporting panic hook, a no-op with `no-panic-hook`
+pub fn install() {
+    #[cfg(not(feature = "no-panic-hook"))]
+    std::panic::set_hook(Box::new(|info| {
+        let message = panic_message(info.payload(), info.location());
+        crate::wasm_dbg_str(&format!("panicked at {}\n", message));
+        // the mock host unwinds on a failed require, which would abort inside the hook
+        #[cfg(not(feature = "mock-host"))]
+        unsafe {
+            crate::require(false)
+        };
+    }));
+}
+
+/// Report the panic and fail, usable as the body of a `#[panic_handler]`
+pub fn handle_panic(_info: &core::panic::PanicInfo) -> ! {
+    #[cfg(not(feature = "no-panic-hook"))]
+    crate::wasm_dbg_str(&format!("{}\n", _info));
+    unsafe { crate::require(false) };
+    std::process::abort()
+}
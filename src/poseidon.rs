@@ -1,90 +1,300 @@
-use crate::poseidon_finalize;
-use crate::poseidon_new;
-use crate::poseidon_push;
+use crate::host::{Host, ZkWasmHost};
+use crate::session::{Session, SessionGuard};
 
-pub struct PoseidonHasher(u64);
+/// Hasher over the host poseidon functions.
+///
+/// The host keeps a single hashing state, so a hasher holds the poseidon session from `new`
+/// until `finalize` (or until it is dropped); see `crate::session`. A hasher created with
+/// `resumable` can be suspended with `snapshot` to use other host functions in between.
+pub struct PoseidonHasher<H: Host = ZkWasmHost> {
+    count: u64,
+    absorbed: Option<Vec<u64>>,
+    session: SessionGuard<H>,
+}
 
-impl PoseidonHasher {
-    pub fn new() -> Self {
-        unsafe {
-            poseidon_new(1u64);
-        }
-        PoseidonHasher(0u64)
-    }
-    pub fn hash(data: &[u64], padding: bool) -> [u64; 4] {
-        let mut hasher = Self::new();
-        if padding {
-            let group = data.len() / 3;
-            let mut j = 0;
-            for i in 0..group {
-                j = i * 3;
-                hasher.update(data[j]);
-                hasher.update(data[j + 1]);
-                hasher.update(data[j + 2]);
-                hasher.update(0u64);
+/// Input absorbed by a suspended hasher, see `PoseidonHasher::snapshot`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoseidonSnapshot {
+    absorbed: Vec<u64>,
+}
+
+impl PoseidonSnapshot {
+    pub fn absorbed(&self) -> &[u64] {
+        &self.absorbed
+    }
+}
+
+/// How `PoseidonHasher::hash` lays out its input limbs.
+///
+/// The hasher absorbs limbs four at a time as one field element, so four arbitrary limbs may
+/// exceed the modulus; the packing modes keep every element below 2^192.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Padding {
+    /// Limbs are absorbed as they are
+    None,
+    /// The original packing: a zero limb after every 3 limbs, but inputs shorter than 3 limbs
+    /// are dropped entirely. Only kept to read trees written with it.
+    Legacy,
+    /// Every group of up to 3 limbs is followed by zero limbs up to 4
+    Fixed3of4,
+    /// The number of limbs in a group of its own, then the input as `Fixed3of4`, so that
+    /// inputs differing only by trailing zero limbs hash differently
+    LengthPrefixed,
+}
+
+impl Padding {
+    /// Feed the limbs `data` is absorbed as to `push`, shared by the guest and native hashers
+    pub fn pad(self, data: &[u64], mut push: impl FnMut(u64)) {
+        match self {
+            Padding::None => {
+                for d in data {
+                    push(*d);
+                }
+            }
+            Padding::Legacy => {
+                let group = data.len() / 3;
+                let mut j = 0;
+                for i in 0..group {
+                    j = i * 3;
+                    push(data[j]);
+                    push(data[j + 1]);
+                    push(data[j + 2]);
+                    push(0u64);
+                }
+                j += 3;
+                for d in data.iter().skip(j) {
+                    push(*d);
+                }
             }
-            j += 3;
-            for i in j..data.len() {
-                hasher.update(data[i]);
+            Padding::Fixed3of4 => {
+                for group in data.chunks(3) {
+                    for d in group {
+                        push(*d);
+                    }
+                    for _ in group.len()..4 {
+                        push(0u64);
+                    }
+                }
             }
-        } else {
-            for d in data {
-                hasher.update(*d);
+            Padding::LengthPrefixed => {
+                push(data.len() as u64);
+                push(0u64);
+                push(0u64);
+                push(0u64);
+                Padding::Fixed3of4.pad(data, push);
             }
         }
+    }
+}
+
+impl PoseidonHasher {
+    pub fn new() -> Self {
+        Self::new_with_host()
+    }
+    pub fn resumable() -> Self {
+        Self::resumable_with_host()
+    }
+    pub fn resume(snapshot: PoseidonSnapshot) -> Self {
+        Self::resume_with_host(snapshot)
+    }
+    pub fn hash(data: &[u64], padding: Padding) -> [u64; 4] {
+        Self::hash_with_host(data, padding)
+    }
+    pub fn hash_bytes(bytes: &[u8]) -> [u64; 4] {
+        Self::hash_bytes_with_host(bytes)
+    }
+    pub fn with_domain(tag: u64) -> Self {
+        Self::with_domain_with_host(tag)
+    }
+    pub fn hash_with_domain(tag: u64, data: &[u64], padding: Padding) -> [u64; 4] {
+        Self::hash_with_domain_with_host(tag, data, padding)
+    }
+}
+
+/// Domain tags of the hashes the sdk computes, under `HashVersion::V1` for the data structures
+pub mod domain {
+    /// Data of a leaf set with `Merkle::set`, which is also its cache key
+    pub const MERKLE_LEAF: u64 = 1;
+    /// Leaf and sub tree entries of a `KeyValueMap`
+    pub const SMT_NODE: u64 = 2;
+    /// Messages signed with a `JubjubSignature`
+    pub const SIGNATURE: u64 = 3;
+    /// Challenges of a `Transcript`
+    pub const TRANSCRIPT: u64 = 4;
+    /// Chunks of a blob, see `crate::blob`
+    pub const BLOB_CHUNK: u64 = 5;
+    /// Leaves of a `MerkleProof` tree
+    pub const PROOF_LEAF: u64 = 6;
+    /// Inner nodes of a `MerkleProof` tree
+    pub const PROOF_NODE: u64 = 7;
+    /// Tags from here on are free for applications
+    pub const USER: u64 = 1 << 32;
+}
+
+/// Which hashing scheme the sdk data structures use.
+///
+/// `V0` is the original untagged hashing and stays the default so that existing roots remain
+/// valid; `V1` hashes every preimage in its own `domain`, so that a preimage of one kind can not
+/// be passed off as another.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashVersion {
+    #[default]
+    V0,
+    V1,
+}
+
+impl HashVersion {
+    pub fn hash(self, tag: u64, data: &[u64], padding: Padding) -> [u64; 4] {
+        self.hash_with_host::<ZkWasmHost>(tag, data, padding)
+    }
+    /// Hash `data`, in the domain `tag` unless this is `V0`
+    pub fn hash_with_host<H: Host>(self, tag: u64, data: &[u64], padding: Padding) -> [u64; 4] {
+        match self {
+            HashVersion::V0 => PoseidonHasher::<H>::hash_with_host(data, padding),
+            HashVersion::V1 => PoseidonHasher::<H>::hash_with_domain_with_host(tag, data, padding),
+        }
+    }
+}
+
+/// Field-safe limbs of a byte string: bytes are packed little endian into limbs of 8 bytes,
+/// followed by one limb with the byte length, and every 3 limbs are followed by a zero limb so
+/// that each group of 4 limbs absorbed as one field element stays below 2^192.
+pub fn pack_bytes(bytes: &[u8]) -> Vec<u64> {
+    let mut limbs: Vec<u64> = bytes
+        .chunks(8)
+        .map(|c| {
+            let mut buf = [0u8; 8];
+            buf[..c.len()].copy_from_slice(c);
+            u64::from_le_bytes(buf)
+        })
+        .collect();
+    limbs.push(bytes.len() as u64);
+    limbs
+        .chunks(3)
+        .flat_map(|c| {
+            let mut group = [0u64; 4];
+            group[..c.len()].copy_from_slice(c);
+            group
+        })
+        .collect()
+}
+
+impl<H: Host> Default for PoseidonHasher<H> {
+    fn default() -> Self {
+        Self::new_with_host()
+    }
+}
+
+impl<H: Host> PoseidonHasher<H> {
+    pub fn new_with_host() -> Self {
+        let session = SessionGuard::begin(Session::Poseidon);
+        H::poseidon_new(1u64);
+        PoseidonHasher {
+            count: 0,
+            absorbed: None,
+            session,
+        }
+    }
+    /// Hasher that keeps its input, so that it can be suspended with `snapshot`
+    pub fn resumable_with_host() -> Self {
+        let mut hasher = Self::new_with_host();
+        hasher.absorbed = Some(vec![]);
+        hasher
+    }
+    /// Suspend a resumable hasher and close its session
+    pub fn snapshot(mut self) -> PoseidonSnapshot {
+        H::require(self.absorbed.is_some());
+        PoseidonSnapshot {
+            absorbed: self.absorbed.take().unwrap(),
+        }
+    }
+    /// Continue a suspended hasher, the input absorbed so far is absorbed again
+    pub fn resume_with_host(snapshot: PoseidonSnapshot) -> Self {
+        let mut hasher = Self::resumable_with_host();
+        for v in snapshot.absorbed {
+            hasher.update(v);
+        }
+        hasher
+    }
+    pub fn hash_with_host(data: &[u64], padding: Padding) -> [u64; 4] {
+        let mut hasher = Self::new_with_host();
+        hasher.update_padded(data, padding);
+        hasher.finalize()
+    }
+    /// Hasher whose input starts with the domain `tag`, absorbed as a group of 4 limbs
+    pub fn with_domain_with_host(tag: u64) -> Self {
+        let mut hasher = Self::new_with_host();
+        hasher.update(tag);
+        hasher.update(0);
+        hasher.update(0);
+        hasher.update(0);
+        hasher
+    }
+    /// Same as `hash_with_host` in the domain `tag`
+    pub fn hash_with_domain_with_host(tag: u64, data: &[u64], padding: Padding) -> [u64; 4] {
+        let mut hasher = Self::with_domain_with_host(tag);
+        hasher.update_padded(data, padding);
         hasher.finalize()
     }
+    fn update_padded(&mut self, data: &[u64], padding: Padding) {
+        padding.pad(data, |v| self.update(v));
+    }
+    pub fn hash_bytes_with_host(bytes: &[u8]) -> [u64; 4] {
+        let mut hasher = Self::new_with_host();
+        hasher.update_bytes(bytes);
+        hasher.finalize()
+    }
+    /// Absorb `pack_bytes(bytes)`, after zero limbs aligning the stream to a group of 4
+    pub fn update_bytes(&mut self, bytes: &[u8]) {
+        while self.count & 0x3 != 0 {
+            self.update(0);
+        }
+        for v in pack_bytes(bytes) {
+            self.update(v);
+        }
+    }
     pub fn update(&mut self, v: u64) {
-        unsafe {
-            poseidon_push(v);
+        if let Some(absorbed) = self.absorbed.as_mut() {
+            absorbed.push(v);
         }
-        self.0 += 1;
-        if self.0 == 32 {
-            unsafe {
-                poseidon_finalize();
-                poseidon_finalize();
-                poseidon_finalize();
-                poseidon_finalize();
-                poseidon_new(0u64);
-            }
-            self.0 = 0;
+        H::poseidon_push(v);
+        self.count += 1;
+        if self.count == 32 {
+            H::poseidon_finalize();
+            H::poseidon_finalize();
+            H::poseidon_finalize();
+            H::poseidon_finalize();
+            H::poseidon_new(0u64);
+            self.count = 0;
         }
     }
     pub fn finalize(&mut self) -> [u64; 4] {
-        if (self.0 & 0x3) != 0 {
-            for _ in (self.0 & 0x3)..4 {
-                unsafe {
-                    poseidon_push(0);
-                }
-                self.0 += 1;
+        if (self.count & 0x3) != 0 {
+            for _ in (self.count & 0x3)..4 {
+                H::poseidon_push(0);
+                self.count += 1;
             }
         }
-        if self.0 == 32 {
-            unsafe {
-                poseidon_finalize();
-                poseidon_finalize();
-                poseidon_finalize();
-                poseidon_finalize();
-                poseidon_new(0u64);
-            }
-            self.0 = 0;
-        }
-        unsafe {
-            poseidon_push(1);
-        }
-        self.0 += 1;
-        for _ in self.0..32 {
-            unsafe {
-                poseidon_push(0);
-            }
+        if self.count == 32 {
+            H::poseidon_finalize();
+            H::poseidon_finalize();
+            H::poseidon_finalize();
+            H::poseidon_finalize();
+            H::poseidon_new(0u64);
+            self.count = 0;
         }
-        unsafe {
-            [
-                poseidon_finalize(),
-                poseidon_finalize(),
-                poseidon_finalize(),
-                poseidon_finalize(),
-            ]
+        H::poseidon_push(1);
+        self.count += 1;
+        for _ in self.count..32 {
+            H::poseidon_push(0);
         }
+        let digest = [
+            H::poseidon_finalize(),
+            H::poseidon_finalize(),
+            H::poseidon_finalize(),
+            H::poseidon_finalize(),
+        ];
+        self.session.release();
+        digest
     }
 }
@@ -0,0 +1,87 @@
+//! Trace size profiling of named scopes.
+//!
+//! `profile!("smt_set", { kvpair.set(&key, &data) })` evaluates the block and charges the
+//! number of trace rows it took (measured with `wasm_trace_size`) to the scope `smt_set`.
+//! Costs of nested scopes are included in the enclosing ones. `dump` writes the aggregated
+//! scopes, most expensive first, to the debug output.
+
+use crate::host::{Host, ZkWasmHost};
+use std::cell::RefCell;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScopeStats {
+    pub name: &'static str,
+    pub calls: u64,
+    pub total: u64,
+    pub max: u64,
+}
+
+thread_local! {
+    static SCOPES: RefCell<Vec<ScopeStats>> = const { RefCell::new(vec![]) };
+}
+
+/// Current trace size, the start of a measured scope
+pub fn start() -> u64 {
+    start_with_host::<ZkWasmHost>()
+}
+
+pub fn start_with_host<H: Host>() -> u64 {
+    H::wasm_trace_size()
+}
+
+/// Charge the rows since `start` to `name`
+pub fn finish(name: &'static str, start: u64) {
+    finish_with_host::<ZkWasmHost>(name, start)
+}
+
+pub fn finish_with_host<H: Host>(name: &'static str, start: u64) {
+    let cost = H::wasm_trace_size() - start;
+    SCOPES.with(|scopes| {
+        let mut scopes = scopes.borrow_mut();
+        match scopes.iter_mut().find(|s| s.name == name) {
+            Some(s) => {
+                s.calls += 1;
+                s.total += cost;
+                s.max = s.max.max(cost);
+            }
+            None => scopes.push(ScopeStats {
+                name,
+                calls: 1,
+                total: cost,
+                max: cost,
+            }),
+        }
+    })
+}
+
+/// Scopes measured so far, sorted by decreasing total cost
+pub fn report() -> Vec<ScopeStats> {
+    let mut scopes = SCOPES.with(|scopes| scopes.borrow().clone());
+    scopes.sort_by(|a, b| b.total.cmp(&a.total).then(a.name.cmp(b.name)));
+    scopes
+}
+
+pub fn reset() {
+    SCOPES.with(|scopes| scopes.borrow_mut().clear());
+}
+
+/// Write the report to the debug output
+pub fn dump() {
+    if !crate::logger::ENABLED {
+        return;
+    }
+    crate::wasm_dbg_str("scope calls total max\n");
+    for s in report() {
+        crate::wasm_dbg_str(&format!("{} {} {} {}\n", s.name, s.calls, s.total, s.max));
+    }
+}
+
+#[macro_export]
+macro_rules! profile {
+    ($name:literal, $body:block) => {{
+        let start = $crate::profile::start();
+        let r = $body;
+        $crate::profile::finish($name, start);
+        r
+    }};
+}
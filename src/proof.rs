@@ -0,0 +1,77 @@
+use crate::host::{Host, ZkWasmHost};
+use crate::jubjub::MODULUS;
+use crate::poseidon::{domain, Padding, PoseidonHasher};
+use primitive_types::U256;
+
+/// Inclusion proof of a binary poseidon merkle tree that is not backed by the host merkle db.
+///
+/// `leaf` is the leaf value, a field element, hashed into the tree under `domain::PROOF_LEAF`;
+/// inner nodes hash the 4 limb hashes of their children under `domain::PROOF_NODE`, so a node
+/// can not be passed off as a leaf. `siblings` go from the leaf up to the root and bit `i` of
+/// `index` tells whether the path goes right at height `i`.
+///
+/// Only roots of `offchain::proof::PoseidonTree` can be proven this way. The host merkle tree
+/// (`Merkle`, `offchain::merkle::MerkleDb`) hashes with a poseidon instance the guest has no
+/// access to; its leaves are read and proven through the host merkle functions instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub leaf: [u64; 4],
+    pub index: u64,
+    pub siblings: Vec<[u64; 4]>,
+}
+
+/// Tree node of a leaf value
+pub fn leaf_hash(leaf: &[u64; 4]) -> [u64; 4] {
+    leaf_hash_with_host::<ZkWasmHost>(leaf)
+}
+
+pub fn leaf_hash_with_host<H: Host>(leaf: &[u64; 4]) -> [u64; 4] {
+    PoseidonHasher::<H>::hash_with_domain_with_host(domain::PROOF_LEAF, leaf, Padding::None)
+}
+
+/// Parent of two nodes
+pub fn node_hash(left: &[u64; 4], right: &[u64; 4]) -> [u64; 4] {
+    node_hash_with_host::<ZkWasmHost>(left, right)
+}
+
+pub fn node_hash_with_host<H: Host>(left: &[u64; 4], right: &[u64; 4]) -> [u64; 4] {
+    let mut data = [0; 8];
+    data[..4].copy_from_slice(left);
+    data[4..].copy_from_slice(right);
+    PoseidonHasher::<H>::hash_with_domain_with_host(domain::PROOF_NODE, &data, Padding::None)
+}
+
+impl MerkleProof {
+    pub fn verify(&self, root: &[u64; 4], depth: usize) -> bool {
+        self.verify_with_host::<ZkWasmHost>(root, depth)
+    }
+
+    pub fn compute_root(&self) -> [u64; 4] {
+        self.compute_root_with_host::<ZkWasmHost>()
+    }
+
+    /// Whether the proof is well formed for a tree of `depth` and its path leads to `root`
+    pub fn verify_with_host<H: Host>(&self, root: &[u64; 4], depth: usize) -> bool {
+        if self.siblings.len() != depth || depth > 64 || (depth < 64 && self.index >> depth != 0) {
+            return false;
+        }
+        let modulus = U256(MODULUS);
+        if U256(self.leaf) >= modulus || self.siblings.iter().any(|s| U256(*s) >= modulus) {
+            return false;
+        }
+        self.compute_root_with_host::<H>() == *root
+    }
+
+    /// Root obtained by hashing the leaf up along the siblings
+    pub fn compute_root_with_host<H: Host>(&self) -> [u64; 4] {
+        let mut node = leaf_hash_with_host::<H>(&self.leaf);
+        for (height, sibling) in self.siblings.iter().enumerate() {
+            node = if (self.index >> height) & 1 == 0 {
+                node_hash_with_host::<H>(&node, sibling)
+            } else {
+                node_hash_with_host::<H>(sibling, &node)
+            };
+        }
+        node
+    }
+}
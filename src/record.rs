@@ -0,0 +1,624 @@
+//! Recording and replaying of host calls.
+//!
+//! `Recorder<H>` forwards every call to `H` and appends it to a per-thread log, so a failing
+//! proof can be traced back to the exact call sequence the guest issued. With this feature
+//! enabled the default `ZkWasmHost` is a `Recorder`, so every SDK type on the default host is
+//! logged without changes to the guest. A log can be encoded
+//! to a compact binary form (`encode`/`decode`) or dumped as JSON (`to_json`), and fed back
+//! through the `Replay` host, which answers from the log and remembers the first call that
+//! does not match it. On a real guest `dump` writes the JSON log to the debug output.
+
+use crate::host::{Host, ImportHost};
+use std::cell::RefCell;
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A host import, the discriminant is the opcode in the binary log
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum HostOp {
+    WasmInput,
+    WasmOutput,
+    WasmReadContext,
+    WasmWriteContext,
+    Require,
+    WasmDbg,
+    WasmDbgChar,
+    MerkleSetroot,
+    MerkleAddress,
+    MerkleSet,
+    MerkleGet,
+    MerkleGetroot,
+    MerkleFetchData,
+    MerklePutData,
+    PoseidonNew,
+    PoseidonPush,
+    PoseidonFinalize,
+    BabyjubjubSumNew,
+    BabyjubjubSumPush,
+    BabyjubjubSumFinalize,
+    CacheSetMode,
+    CacheSetHash,
+    CacheStoreData,
+    CacheFetchData,
+    WitnessInsert,
+    WitnessPop,
+    WitnessSetIndex,
+    WitnessIndexedPop,
+    WitnessIndexedInsert,
+    WitnessIndexedPush,
+    WasmTraceSize,
+    MerkleSetdepth,
+}
+
+use HostOp::*;
+
+const ALL_OPS: [HostOp; 32] = [
+    WasmInput,
+    WasmOutput,
+    WasmReadContext,
+    WasmWriteContext,
+    Require,
+    WasmDbg,
+    WasmDbgChar,
+    MerkleSetroot,
+    MerkleAddress,
+    MerkleSet,
+    MerkleGet,
+    MerkleGetroot,
+    MerkleFetchData,
+    MerklePutData,
+    PoseidonNew,
+    PoseidonPush,
+    PoseidonFinalize,
+    BabyjubjubSumNew,
+    BabyjubjubSumPush,
+    BabyjubjubSumFinalize,
+    CacheSetMode,
+    CacheSetHash,
+    CacheStoreData,
+    CacheFetchData,
+    WitnessInsert,
+    WitnessPop,
+    WitnessSetIndex,
+    WitnessIndexedPop,
+    WitnessIndexedInsert,
+    WitnessIndexedPush,
+    WasmTraceSize,
+    MerkleSetdepth,
+];
+
+impl HostOp {
+    /// Name of the import
+    pub fn name(self) -> &'static str {
+        match self {
+            WasmInput => "wasm_input",
+            WasmOutput => "wasm_output",
+            WasmReadContext => "wasm_read_context",
+            WasmWriteContext => "wasm_write_context",
+            Require => "require",
+            WasmDbg => "wasm_dbg",
+            WasmDbgChar => "wasm_dbg_char",
+            MerkleSetroot => "merkle_setroot",
+            MerkleAddress => "merkle_address",
+            MerkleSet => "merkle_set",
+            MerkleGet => "merkle_get",
+            MerkleGetroot => "merkle_getroot",
+            MerkleFetchData => "merkle_fetch_data",
+            MerklePutData => "merkle_put_data",
+            PoseidonNew => "poseidon_new",
+            PoseidonPush => "poseidon_push",
+            PoseidonFinalize => "poseidon_finalize",
+            BabyjubjubSumNew => "babyjubjub_sum_new",
+            BabyjubjubSumPush => "babyjubjub_sum_push",
+            BabyjubjubSumFinalize => "babyjubjub_sum_finalize",
+            CacheSetMode => "cache_set_mode",
+            CacheSetHash => "cache_set_hash",
+            CacheStoreData => "cache_store_data",
+            CacheFetchData => "cache_fetch_data",
+            WitnessInsert => "wasm_witness_insert",
+            WitnessPop => "wasm_witness_pop",
+            WitnessSetIndex => "wasm_witness_set_index",
+            WitnessIndexedPop => "wasm_witness_indexed_pop",
+            WitnessIndexedInsert => "wasm_witness_indexed_insert",
+            WitnessIndexedPush => "wasm_witness_indexed_push",
+            WasmTraceSize => "wasm_trace_size",
+            MerkleSetdepth => "merkle_setdepth",
+        }
+    }
+
+    /// Whether the import returns a value, every other import takes exactly one argument
+    pub fn returns(self) -> bool {
+        matches!(
+            self,
+            WasmReadContext
+                | MerkleGet
+                | MerkleGetroot
+                | MerkleFetchData
+                | PoseidonFinalize
+                | BabyjubjubSumFinalize
+                | CacheFetchData
+                | WitnessPop
+                | WitnessIndexedPop
+                | WasmTraceSize
+        )
+    }
+
+    /// Whether the import takes an argument
+    pub fn takes_arg(self) -> bool {
+        self == WasmInput || !self.returns()
+    }
+
+    pub fn from_code(code: u8) -> Option<Self> {
+        ALL_OPS.get(code as usize).copied()
+    }
+}
+
+/// One logged host call
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HostCall {
+    pub op: HostOp,
+    pub arg: Option<u64>,
+    pub ret: Option<u64>,
+}
+
+impl fmt::Display for HostCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}(", self.op.name())?;
+        if let Some(arg) = self.arg {
+            write!(f, "{}", arg)?;
+        }
+        write!(f, ")")?;
+        if let Some(ret) = self.ret {
+            write!(f, " -> {}", ret)?;
+        }
+        Ok(())
+    }
+}
+
+/// Binary log: per call the opcode, then the argument and the return value as little-endian
+/// u64 if the import has them.
+pub fn encode(calls: &[HostCall]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for call in calls {
+        bytes.push(call.op as u8);
+        if let Some(arg) = call.arg {
+            bytes.extend_from_slice(&arg.to_le_bytes());
+        }
+        if let Some(ret) = call.ret {
+            bytes.extend_from_slice(&ret.to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// Inverse of `encode`, `None` if the log is malformed
+pub fn decode(mut bytes: &[u8]) -> Option<Vec<HostCall>> {
+    fn next_u64(bytes: &mut &[u8]) -> Option<u64> {
+        let v = u64::from_le_bytes(bytes.get(..8)?.try_into().unwrap());
+        *bytes = &bytes[8..];
+        Some(v)
+    }
+    let mut calls = vec![];
+    while let Some((code, rest)) = bytes.split_first() {
+        let op = HostOp::from_code(*code)?;
+        bytes = rest;
+        let arg = if op.takes_arg() {
+            Some(next_u64(&mut bytes)?)
+        } else {
+            None
+        };
+        let ret = if op.returns() {
+            Some(next_u64(&mut bytes)?)
+        } else {
+            None
+        };
+        calls.push(HostCall { op, arg, ret });
+    }
+    Some(calls)
+}
+
+/// JSON array of `{"op": .., "arg": .., "ret": ..}` objects, absent fields are omitted
+pub fn to_json(calls: &[HostCall]) -> String {
+    let entries: Vec<String> = calls
+        .iter()
+        .map(|call| {
+            let mut entry = format!("{{\"op\":\"{}\"", call.op.name());
+            if let Some(arg) = call.arg {
+                entry += &format!(",\"arg\":{}", arg);
+            }
+            if let Some(ret) = call.ret {
+                entry += &format!(",\"ret\":{}", ret);
+            }
+            entry + "}"
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+thread_local! {
+    static LOG: RefCell<Vec<HostCall>> = const { RefCell::new(vec![]) };
+}
+
+/// Take the calls recorded on this thread so far
+pub fn take_log() -> Vec<HostCall> {
+    LOG.with(|log| std::mem::take(&mut *log.borrow_mut()))
+}
+
+/// Number of calls recorded on this thread so far
+pub fn log_len() -> usize {
+    LOG.with(|log| log.borrow().len())
+}
+
+/// Write the calls recorded on this thread so far as JSON to the debug output, the log is kept
+/// and the dump itself is not recorded
+pub fn dump() {
+    let json = LOG.with(|log| to_json(&log.borrow()));
+    crate::wasm_dbg_str(&json);
+    crate::wasm_dbg_str("\n");
+}
+
+fn record(op: HostOp, arg: Option<u64>, ret: Option<u64>) {
+    LOG.with(|log| log.borrow_mut().push(HostCall { op, arg, ret }))
+}
+
+fn record_arg(op: HostOp, arg: u64) {
+    record(op, Some(arg), None)
+}
+
+fn record_ret(op: HostOp, ret: u64) -> u64 {
+    record(op, None, Some(ret));
+    ret
+}
+
+/// Host forwarding to `H` and logging every call
+pub struct Recorder<H: Host = ImportHost>(PhantomData<H>);
+
+impl<H: Host> Host for Recorder<H> {
+    fn wasm_input(is_public: u32) -> u64 {
+        let ret = H::wasm_input(is_public);
+        record(WasmInput, Some(is_public as u64), Some(ret));
+        ret
+    }
+    fn wasm_output(v: u64) {
+        H::wasm_output(v);
+        record_arg(WasmOutput, v)
+    }
+    fn wasm_read_context() -> u64 {
+        record_ret(WasmReadContext, H::wasm_read_context())
+    }
+    fn wasm_write_context(v: u64) {
+        H::wasm_write_context(v);
+        record_arg(WasmWriteContext, v)
+    }
+    fn require(cond: bool) {
+        // logged first so that a failing require is the last entry
+        record_arg(Require, cond as u64);
+        H::require(cond)
+    }
+    fn wasm_dbg(v: u64) {
+        H::wasm_dbg(v);
+        record_arg(WasmDbg, v)
+    }
+    fn wasm_dbg_char(v: u64) {
+        H::wasm_dbg_char(v);
+        record_arg(WasmDbgChar, v)
+    }
+    fn wasm_trace_size() -> u64 {
+        record_ret(WasmTraceSize, H::wasm_trace_size())
+    }
+
+    fn merkle_setroot(x: u64) {
+        H::merkle_setroot(x);
+        record_arg(MerkleSetroot, x)
+    }
+    fn merkle_address(x: u64) {
+        H::merkle_address(x);
+        record_arg(MerkleAddress, x)
+    }
+    fn merkle_setdepth(x: u64) {
+        H::merkle_setdepth(x);
+        record_arg(MerkleSetdepth, x)
+    }
+    fn merkle_set(x: u64) {
+        H::merkle_set(x);
+        record_arg(MerkleSet, x)
+    }
+    fn merkle_get() -> u64 {
+        record_ret(MerkleGet, H::merkle_get())
+    }
+    fn merkle_getroot() -> u64 {
+        record_ret(MerkleGetroot, H::merkle_getroot())
+    }
+    fn merkle_fetch_data() -> u64 {
+        record_ret(MerkleFetchData, H::merkle_fetch_data())
+    }
+    fn merkle_put_data(x: u64) {
+        H::merkle_put_data(x);
+        record_arg(MerklePutData, x)
+    }
+
+    fn poseidon_new(x: u64) {
+        H::poseidon_new(x);
+        record_arg(PoseidonNew, x)
+    }
+    fn poseidon_push(x: u64) {
+        H::poseidon_push(x);
+        record_arg(PoseidonPush, x)
+    }
+    fn poseidon_finalize() -> u64 {
+        record_ret(PoseidonFinalize, H::poseidon_finalize())
+    }
+
+    fn babyjubjub_sum_new(x: u64) {
+        H::babyjubjub_sum_new(x);
+        record_arg(BabyjubjubSumNew, x)
+    }
+    fn babyjubjub_sum_push(x: u64) {
+        H::babyjubjub_sum_push(x);
+        record_arg(BabyjubjubSumPush, x)
+    }
+    fn babyjubjub_sum_finalize() -> u64 {
+        record_ret(BabyjubjubSumFinalize, H::babyjubjub_sum_finalize())
+    }
+
+    fn cache_set_mode(x: u64) {
+        H::cache_set_mode(x);
+        record_arg(CacheSetMode, x)
+    }
+    fn cache_set_hash(x: u64) {
+        H::cache_set_hash(x);
+        record_arg(CacheSetHash, x)
+    }
+    fn cache_store_data(x: u64) {
+        H::cache_store_data(x);
+        record_arg(CacheStoreData, x)
+    }
+    fn cache_fetch_data() -> u64 {
+        record_ret(CacheFetchData, H::cache_fetch_data())
+    }
+
+    #[cfg(feature = "witness")]
+    fn wasm_witness_insert(u: u64) {
+        H::wasm_witness_insert(u);
+        record_arg(WitnessInsert, u)
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_pop() -> u64 {
+        record_ret(WitnessPop, H::wasm_witness_pop())
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_set_index(x: u64) {
+        H::wasm_witness_set_index(x);
+        record_arg(WitnessSetIndex, x)
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_pop() -> u64 {
+        record_ret(WitnessIndexedPop, H::wasm_witness_indexed_pop())
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_insert(x: u64) {
+        H::wasm_witness_indexed_insert(x);
+        record_arg(WitnessIndexedInsert, x)
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_push(x: u64) {
+        H::wasm_witness_indexed_push(x);
+        record_arg(WitnessIndexedPush, x)
+    }
+}
+
+/// First call of a replay that does not match the log
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// Position of the call in the log
+    pub index: usize,
+    /// The logged call, `None` if the log was exhausted
+    pub expected: Option<HostCall>,
+    /// The call that was made, `ret` is what the replay answered
+    pub actual: HostCall,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.expected {
+            Some(expected) => write!(
+                f,
+                "host call {}: expected {}, got {}",
+                self.index, expected, self.actual
+            ),
+            None => write!(
+                f,
+                "host call {}: log exhausted, got {}",
+                self.index, self.actual
+            ),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ReplayState {
+    calls: Vec<HostCall>,
+    cursor: usize,
+    divergence: Option<Divergence>,
+}
+
+thread_local! {
+    static REPLAY: RefCell<ReplayState> = RefCell::new(ReplayState::default());
+}
+
+/// Host answering from a recorded log instead of a runtime.
+///
+/// Calls are matched by position. Results of a call whose import differs from the log are
+/// zero, results of a call that only differs in its argument are the logged ones; either
+/// way the first such call is kept as the `divergence`. `require` panics like the real host.
+pub struct Replay;
+
+impl Replay {
+    /// Start replaying `calls` on this thread
+    pub fn load(calls: Vec<HostCall>) {
+        REPLAY.with(|r| {
+            *r.borrow_mut() = ReplayState {
+                calls,
+                ..ReplayState::default()
+            }
+        })
+    }
+
+    /// First mismatch since `load`
+    pub fn divergence() -> Option<Divergence> {
+        REPLAY.with(|r| r.borrow().divergence.clone())
+    }
+
+    /// Whether every logged call has been replayed
+    pub fn finished() -> bool {
+        REPLAY.with(|r| {
+            let r = r.borrow();
+            r.cursor == r.calls.len()
+        })
+    }
+
+    fn step(op: HostOp, arg: Option<u64>) -> u64 {
+        REPLAY.with(|r| {
+            let mut r = r.borrow_mut();
+            let index = r.cursor;
+            r.cursor += 1;
+            let expected = r.calls.get(index).copied();
+            let ret = match expected {
+                Some(call) if call.op == op => call.ret.unwrap_or(0),
+                _ => 0,
+            };
+            let matches = matches!(expected, Some(call) if call.op == op && call.arg == arg);
+            if !matches && r.divergence.is_none() {
+                let ret = if op.returns() { Some(ret) } else { None };
+                r.divergence = Some(Divergence {
+                    index,
+                    expected,
+                    actual: HostCall { op, arg, ret },
+                });
+            }
+            ret
+        })
+    }
+
+    fn call(op: HostOp, arg: u64) {
+        Self::step(op, Some(arg));
+    }
+
+    fn get(op: HostOp) -> u64 {
+        Self::step(op, None)
+    }
+}
+
+impl Host for Replay {
+    fn wasm_input(is_public: u32) -> u64 {
+        Self::step(WasmInput, Some(is_public as u64))
+    }
+    fn wasm_output(v: u64) {
+        Self::call(WasmOutput, v)
+    }
+    fn wasm_read_context() -> u64 {
+        Self::get(WasmReadContext)
+    }
+    fn wasm_write_context(v: u64) {
+        Self::call(WasmWriteContext, v)
+    }
+    fn require(cond: bool) {
+        Self::call(Require, cond as u64);
+        if !cond {
+            panic!("require failed");
+        }
+    }
+    fn wasm_dbg(v: u64) {
+        Self::call(WasmDbg, v)
+    }
+    fn wasm_dbg_char(v: u64) {
+        Self::call(WasmDbgChar, v)
+    }
+    fn wasm_trace_size() -> u64 {
+        Self::get(WasmTraceSize)
+    }
+
+    fn merkle_setroot(x: u64) {
+        Self::call(MerkleSetroot, x)
+    }
+    fn merkle_address(x: u64) {
+        Self::call(MerkleAddress, x)
+    }
+    fn merkle_setdepth(x: u64) {
+        Self::call(MerkleSetdepth, x)
+    }
+    fn merkle_set(x: u64) {
+        Self::call(MerkleSet, x)
+    }
+    fn merkle_get() -> u64 {
+        Self::get(MerkleGet)
+    }
+    fn merkle_getroot() -> u64 {
+        Self::get(MerkleGetroot)
+    }
+    fn merkle_fetch_data() -> u64 {
+        Self::get(MerkleFetchData)
+    }
+    fn merkle_put_data(x: u64) {
+        Self::call(MerklePutData, x)
+    }
+
+    fn poseidon_new(x: u64) {
+        Self::call(PoseidonNew, x)
+    }
+    fn poseidon_push(x: u64) {
+        Self::call(PoseidonPush, x)
+    }
+    fn poseidon_finalize() -> u64 {
+        Self::get(PoseidonFinalize)
+    }
+
+    fn babyjubjub_sum_new(x: u64) {
+        Self::call(BabyjubjubSumNew, x)
+    }
+    fn babyjubjub_sum_push(x: u64) {
+        Self::call(BabyjubjubSumPush, x)
+    }
+    fn babyjubjub_sum_finalize() -> u64 {
+        Self::get(BabyjubjubSumFinalize)
+    }
+
+    fn cache_set_mode(x: u64) {
+        Self::call(CacheSetMode, x)
+    }
+    fn cache_set_hash(x: u64) {
+        Self::call(CacheSetHash, x)
+    }
+    fn cache_store_data(x: u64) {
+        Self::call(CacheStoreData, x)
+    }
+    fn cache_fetch_data() -> u64 {
+        Self::get(CacheFetchData)
+    }
+
+    #[cfg(feature = "witness")]
+    fn wasm_witness_insert(u: u64) {
+        Self::call(WitnessInsert, u)
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_pop() -> u64 {
+        Self::get(WitnessPop)
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_set_index(x: u64) {
+        Self::call(WitnessSetIndex, x)
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_pop() -> u64 {
+        Self::get(WitnessIndexedPop)
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_insert(x: u64) {
+        Self::call(WitnessIndexedInsert, x)
+    }
+    #[cfg(feature = "witness")]
+    fn wasm_witness_indexed_push(x: u64) {
+        Self::call(WitnessIndexedPush, x)
+    }
+}
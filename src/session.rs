@@ -0,0 +1,94 @@
+//! Exclusive use of the stateful host functions.
+//!
+//! The poseidon, merkle, babyjubjub and cache host functions each keep a session state in the
+//! host, and a call of one family in the middle of a session of another (or of a second
+//! session of the same family) silently corrupts both. Every sdk wrapper holds a
+//! `SessionGuard` for the duration of its session, and opening a second one while one is
+//! active aborts with `ZkError::SessionOverlap`.
+
+use crate::error::{abort_with_host, ZkError};
+use crate::host::Host;
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Session {
+    Poseidon,
+    Merkle,
+    BabyJubjub,
+    Cache,
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Session::Poseidon => "poseidon",
+            Session::Merkle => "merkle",
+            Session::BabyJubjub => "babyjubjub",
+            Session::Cache => "cache",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+thread_local! {
+    static ACTIVE: Cell<Option<Session>> = const { Cell::new(None) };
+}
+
+/// The session currently open, if any
+pub fn active() -> Option<Session> {
+    ACTIVE.with(|a| a.get())
+}
+
+/// Forget the open session, e.g. after a test aborted in the middle of one
+pub fn reset() {
+    ACTIVE.with(|a| a.set(None));
+}
+
+/// Token for the open session, closing it when released or dropped
+pub struct SessionGuard<H: Host> {
+    session: Session,
+    held: bool,
+    host: PhantomData<H>,
+}
+
+impl<H: Host> SessionGuard<H> {
+    /// Open `session`, aborting if another one is active
+    pub fn begin(session: Session) -> Self {
+        if let Some(active) = active() {
+            abort_with_host::<H>(ZkError::SessionOverlap {
+                active,
+                requested: session,
+            });
+        }
+        ACTIVE.with(|a| a.set(Some(session)));
+        SessionGuard {
+            session,
+            held: true,
+            host: PhantomData,
+        }
+    }
+
+    pub fn session(&self) -> Session {
+        self.session
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.held
+    }
+
+    /// Close the session before the guard goes out of scope
+    pub fn release(&mut self) {
+        if self.held {
+            self.held = false;
+            ACTIVE.with(|a| a.set(None));
+        }
+    }
+}
+
+impl<H: Host> Drop for SessionGuard<H> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
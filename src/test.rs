@@ -1,9 +1,4 @@
-extern "C" {
-    //pub fn wasm_input(is_public: u32) -> u64;
-    //pub fn wasm_dbg(v:u64);
-    pub fn require(cond: bool);
-    pub fn wasm_trace_size() -> u64;
-}
+use crate::require;
 
 use crate::jubjub::BabyJubjubPoint;
 use crate::jubjub::JubjubSignature;
@@ -12,9 +7,10 @@ use crate::kvpair::KeyValueMapU64;
 use crate::merkle::Merkle;
 use primitive_types::U256;
 
-use crate::poseidon::PoseidonHasher;
-use wasm_bindgen::prelude::*;
+use crate::poseidon::{Padding, PoseidonHasher};
+use crate::zktest;
 
+#[zktest]
 pub fn test_merkle() {
     let mut hasher = PoseidonHasher::new();
     let data = vec![0x1, 0x1, 2, 2];
@@ -27,9 +23,9 @@ pub fn test_merkle() {
     let mut leaf = [0, 0, 0, 0];
 
     crate::dbg!("testing merkle set 1, index: 0\n");
-    merkle.set(0, &[1, 1, 2, 2], false, None);
+    merkle.set(0, &[1, 1, 2, 2], Padding::None, None);
 
-    let len = merkle.get(0, &mut leaf, &mut [0; 4], false);
+    let len = merkle.get(0, &mut leaf, &mut [0; 4], Padding::None);
 
     unsafe {
         require(len == 4);
@@ -37,10 +33,10 @@ pub fn test_merkle() {
     }
 
     crate::dbg!("testing merkle set 2, index: 0\n");
-    merkle.set(0, &[3, 4, 5, 6, 7], true, None);
+    merkle.set(0, &[3, 4, 5, 6, 7], Padding::Legacy, None);
     let mut leaf = [0, 0, 0, 0, 0];
 
-    let len = merkle.get(0, &mut leaf, &mut [0; 4], true);
+    let len = merkle.get(0, &mut leaf, &mut [0; 4], Padding::Legacy);
 
     unsafe {
         require(len == 5);
@@ -64,7 +60,7 @@ fn test_kvpair_value(
     data_buf: &mut [u64],
     data: &[u64],
 ) {
-    let len = kvpair.get(&key, data_buf);
+    let len = kvpair.get(key, data_buf);
     unsafe {
         require(len as usize == data.len());
         for i in 0..len as usize {
@@ -73,6 +69,7 @@ fn test_kvpair_value(
     }
 }
 
+#[zktest]
 pub fn test_kvpair() {
     let merkle = Merkle::new();
     let mut kvpair = KeyValueMap::new(merkle);
@@ -110,10 +107,7 @@ pub fn test_kvpair() {
     crate::dbg!("testing kvpair key5 ...\n");
     kvpair.set(&key5, &[8, 9]);
     //kvpair.set(&key1, &[5]);
-    let trace_size = unsafe { wasm_trace_size() };
-    kvpair.set(&key1, &[6]);
-    let delta_size = unsafe { wasm_trace_size() - trace_size };
-    crate::dbg!("delta size is {}\n", delta_size);
+    crate::profile!("kvpair_update", { kvpair.set(&key1, &[6]) });
     test_kvpair_value(&mut kvpair, &key1, &mut data_buf, &[6]);
     test_kvpair_value(&mut kvpair, &key2, &mut data_buf, &[2, 3]);
     test_kvpair_value(&mut kvpair, &key3, &mut data_buf, &[4, 5, 6]);
@@ -124,6 +118,7 @@ pub fn test_kvpair() {
     unsafe { require(len == 0) };
 }
 
+#[zktest]
 pub fn test_kvpair_u64() {
     let merkle = Merkle::new();
     let mut kvpair = KeyValueMapU64::new(merkle);
@@ -132,10 +127,7 @@ pub fn test_kvpair_u64() {
         for j in 0..count {
             let key = i + (j << 32);
             let data = i * 16 + j;
-            let trace_size = unsafe { wasm_trace_size() };
-            kvpair.set(key, data);
-            let delta_size = unsafe { wasm_trace_size() - trace_size };
-            crate::dbg!("fill size {}\n", delta_size);
+            crate::profile!("kvpair_u64_set", { kvpair.set(key, data) });
         }
     }
 
@@ -143,10 +135,7 @@ pub fn test_kvpair_u64() {
         for j in 0..count {
             let key = i + (j << 32);
             let data = i * 16 + j;
-            let trace_size = unsafe { wasm_trace_size() };
-            let data_in = kvpair.get(key);
-            let delta_size = unsafe { wasm_trace_size() - trace_size };
-            crate::dbg!("get size is {}\n", delta_size);
+            let data_in = crate::profile!("kvpair_u64_get", { kvpair.get(key) });
             if data != data_in {
                 crate::dbg!("key {} data {}, data_in {}\n", key, data, data_in);
             }
@@ -155,6 +144,7 @@ pub fn test_kvpair_u64() {
     }
 }
 
+#[zktest]
 pub fn test_jubjub() {
     let c = BabyJubjubPoint {
         x: U256([0, 0, 0, 0]),
@@ -207,25 +197,1016 @@ pub fn test_jubjub() {
 
     sig.verify(&pk, &[32195221423877958, 0, 0, 0]);
 }
-#[wasm_bindgen]
-pub fn zkmain() -> i64 {
-    if true {
-        crate::dbg!("testing merkle\n");
-        test_merkle();
-        crate::dbg!("testing jubjub\n");
-        test_jubjub();
-        crate::dbg!("testing kvpair\n");
-        test_kvpair();
-        crate::dbg!("testing kvpair u64\n");
-        test_kvpair_u64();
-    }
-    if true {
+
+/// Digest of `PoseidonHasher::hash` of a single zero limb with `Padding::None`.
+///
+/// It is the squeeze of one zero element asserted by the poseidon test of the zkWasm host
+/// circuits (`0x03f943aa..761c6b`), the conformance check of the guest and native hashers.
+pub const POSEIDON_HOST_VECTOR: [u64; 4] = [
+    10489998365943012459,
+    9278378778967449387,
+    8261072811052264498,
+    286334451915935099,
+];
+
+/// Digests of `PoseidonHasher::hash` of `1..=len` with `Padding::None`, by `len`.
+///
+/// Regression vectors only: they were computed with the native hasher, not taken from the host,
+/// so they catch changes of either hasher but do not show that the host agrees.
+pub const POSEIDON_REGRESSION_VECTORS: [(usize, [u64; 4]); 3] = [
+    (
+        0,
+        [
+            8805135776180314813,
+            11222980172829845254,
+            2456238048421532871,
+            1294639513800839829,
+        ],
+    ),
+    (
+        32,
+        [
+            8252795448406948337,
+            7345349098958443426,
+            17753995874808386792,
+            3400124930766877012,
+        ],
+    ),
+    (
+        80,
+        [
+            4389978653445797607,
+            4055548581349088239,
+            9056123718348331116,
+            2990613205423371881,
+        ],
+    ),
+];
+
+#[zktest]
+pub fn test_poseidon() {
+    let hash = PoseidonHasher::hash(&[0], Padding::None);
+    unsafe { require(hash == POSEIDON_HOST_VECTOR) };
+    for (len, digest) in POSEIDON_REGRESSION_VECTORS {
+        let data: Vec<u64> = (1..=len as u64).collect();
+        let hash = PoseidonHasher::hash(&data, Padding::None);
+        unsafe { require(hash == digest) };
+    }
+}
+
+#[zktest]
+pub fn test_witness() {
+    #[cfg(feature = "witness")]
+    {
+        #[cfg(target_arch = "wasm32")]
         super::witness::test_witness_obj();
         super::witness::test_witness_indexed(0xff);
         super::witness::test_witness_indexed(0x1);
         super::witness::test_witness_indexed(0x2);
         super::witness::test_witness_indexed(0xff);
     }
-    super::dbg!("test done\n");
-    0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock;
+    use crate::Padding;
+
+    #[test]
+    fn default_root_is_empty_tree() {
+        mock::reset();
+        unsafe { crate::require(crate::Merkle::new().root == mock::empty_merkle_root()) };
+    }
+
+    #[test]
+    fn kvpair_u64() {
+        mock::reset();
+        crate::profile::reset();
+        super::test_kvpair_u64();
+        let report = crate::profile::report();
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].name, "kvpair_u64_set");
+        assert!(report
+            .iter()
+            .all(|s| s.calls == 16 && s.max * 16 >= s.total));
+        assert!(report[0].total > report[1].total);
+    }
+
+    #[test]
+    fn zktest_main_lists_tests() {
+        use crate::zktest::{run_all_native, ZkTest};
+        let names: Vec<_> = crate::ZKTESTS.iter().map(|t| t.name).collect();
+        assert_eq!(
+            names,
+            [
+                "test_merkle",
+                "test_jubjub",
+                "test_poseidon",
+                "test_kvpair",
+                "test_kvpair_u64",
+                "test_witness"
+            ]
+        );
+        let tests = [
+            ZkTest {
+                name: "passes",
+                run: || (),
+            },
+            ZkTest {
+                name: "fails",
+                run: || unsafe { crate::require(false) },
+            },
+        ];
+        assert_eq!(
+            run_all_native(&tests),
+            vec![("passes", true), ("fails", false)]
+        );
+    }
+
+    #[test]
+    fn offchain_poseidon_matches_host_vector() {
+        use crate::offchain::poseidon::PoseidonHasher;
+        assert_eq!(
+            PoseidonHasher::hash(&[0], Padding::None),
+            super::POSEIDON_HOST_VECTOR
+        );
+        for (len, digest) in super::POSEIDON_REGRESSION_VECTORS {
+            let data: Vec<u64> = (1..=len as u64).collect();
+            assert_eq!(
+                PoseidonHasher::hash(&data, Padding::None),
+                digest,
+                "len {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn offchain_poseidon_matches_guest() {
+        mock::reset();
+        for len in [0, 1, 2, 3, 4, 5, 7, 8, 31, 32, 33, 63, 64, 65, 100] {
+            let data: Vec<u64> = (0..len)
+                .map(|i| (i as u64).wrapping_mul(0x9e3779b97f4a7c15))
+                .collect();
+            for pad in [
+                Padding::None,
+                Padding::Legacy,
+                Padding::Fixed3of4,
+                Padding::LengthPrefixed,
+            ] {
+                assert_eq!(
+                    crate::PoseidonHasher::hash(&data, pad),
+                    crate::offchain::poseidon::PoseidonHasher::hash(&data, pad),
+                    "len {} pad {:?}",
+                    len,
+                    pad
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn padding_modes() {
+        use crate::PoseidonHasher;
+        mock::reset();
+        let hash = PoseidonHasher::hash;
+        // the legacy mode drops inputs shorter than 3 limbs
+        assert_eq!(hash(&[7, 8], Padding::Legacy), hash(&[], Padding::Legacy));
+        assert_ne!(
+            hash(&[7, 8], Padding::Fixed3of4),
+            hash(&[], Padding::Fixed3of4)
+        );
+        assert_eq!(
+            hash(&[7, 8], Padding::Fixed3of4),
+            hash(&[7, 8, 0], Padding::Fixed3of4)
+        );
+        assert_ne!(
+            hash(&[7, 8], Padding::LengthPrefixed),
+            hash(&[7, 8, 0], Padding::LengthPrefixed)
+        );
+        let data: Vec<u64> = (1..=7).collect();
+        assert_eq!(
+            hash(&data, Padding::Legacy),
+            hash(&data, Padding::Fixed3of4)
+        );
+        assert_eq!(
+            hash(&[1, 2, 3, 0], Padding::None),
+            hash(&[1, 2, 3], Padding::Fixed3of4)
+        );
+    }
+
+    #[test]
+    fn overlapping_sessions_are_rejected() {
+        use crate::session::{self, Session};
+        use crate::{Merkle, PoseidonHasher};
+        mock::reset();
+        let mut merkle = Merkle::new();
+        merkle.set(0, &[1, 2, 3], Padding::Fixed3of4, None);
+
+        let mut hasher = PoseidonHasher::resumable();
+        hasher.update(1);
+        hasher.update(2);
+        assert_eq!(session::active(), Some(Session::Poseidon));
+        let r = std::panic::catch_unwind(|| {
+            Merkle::load(merkle.root).get(0, &mut [0; 4], &mut [0; 4], Padding::Fixed3of4)
+        });
+        assert!(r.is_err());
+        assert!(mock::debug_output()
+            .ends_with("error 6: merkle session during active poseidon session\n"));
+
+        // suspend the hasher around the merkle access
+        let snapshot = hasher.snapshot();
+        assert_eq!(session::active(), None);
+        let len = merkle.get(0, &mut [0; 4], &mut [0; 4], Padding::Fixed3of4);
+        assert_eq!(len, 3);
+        let mut hasher = PoseidonHasher::resume(snapshot);
+        for v in 3..40 {
+            hasher.update(v);
+        }
+        let digest = hasher.finalize();
+        assert_eq!(session::active(), None);
+        let data: Vec<u64> = (1..40).collect();
+        assert_eq!(digest, PoseidonHasher::hash(&data, Padding::None));
+    }
+
+    #[test]
+    fn transcript_challenges() {
+        use crate::offchain::transcript::Transcript as NativeTranscript;
+        use crate::{BabyJubjubPoint, Merkle, Transcript, MODULUS};
+        use primitive_types::U256;
+        mock::reset();
+        let point = BabyJubjubPoint {
+            x: U256([0, 0, 0, 0]),
+            y: U256([1, 0, 0, 0]),
+        };
+        let mut guest = Transcript::new(7);
+        let mut native = NativeTranscript::new(7);
+        guest.append_point(&point);
+        native.append_point(&point);
+        // appending does not hold the host hasher
+        guest.append_hash(&Merkle::new().root);
+        native.append_hash(&Merkle::new().root);
+        let c1 = guest.challenge_scalar();
+        assert_eq!(c1, native.challenge_scalar());
+        assert!(U256(c1) < U256(MODULUS));
+
+        let c2 = guest.challenge_scalar();
+        assert_ne!(c1, c2);
+        assert_eq!(c2, native.challenge_scalar());
+        guest.append_u64(1);
+        native.append_u64(2);
+        assert_ne!(guest.challenge_scalar(), native.challenge_scalar());
+        assert_ne!(
+            Transcript::new(7).challenge_scalar(),
+            Transcript::new(8).challenge_scalar()
+        );
+    }
+
+    #[test]
+    fn poseidon_hash_bytes() {
+        use crate::offchain::poseidon::PoseidonHasher as NativeHasher;
+        use crate::PoseidonHasher;
+        mock::reset();
+        assert_ne!(
+            PoseidonHasher::hash_bytes(b"ab"),
+            PoseidonHasher::hash_bytes(b"ab\0")
+        );
+        assert_ne!(
+            PoseidonHasher::hash_bytes(b""),
+            PoseidonHasher::hash_bytes(b"\0")
+        );
+        for len in [0, 1, 7, 8, 9, 23, 24, 25, 200, 300] {
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 7 + 0xf0) as u8).collect();
+            assert_eq!(
+                PoseidonHasher::hash_bytes(&bytes),
+                NativeHasher::hash_bytes(&bytes),
+                "len {}",
+                len
+            );
+            let mut guest = PoseidonHasher::new();
+            let mut native = NativeHasher::new();
+            guest.update(1);
+            native.update(1);
+            guest.update_bytes(&bytes);
+            native.update_bytes(&bytes);
+            assert_eq!(guest.finalize(), native.finalize(), "len {}", len);
+        }
+    }
+
+    #[test]
+    fn domain_separated_hashing() {
+        use crate::kvpair::KeyValueMap;
+        use crate::offchain::poseidon::PoseidonHasher as NativeHasher;
+        use crate::{domain, HashVersion, JubjubSignature, Merkle, PoseidonHasher, ZkError};
+        mock::reset();
+        let data = [1, 2, 3, 4, 5];
+        let tagged = PoseidonHasher::hash_with_domain(domain::MERKLE_LEAF, &data, Padding::Legacy);
+        assert_ne!(tagged, PoseidonHasher::hash(&data, Padding::Legacy));
+        assert_ne!(
+            tagged,
+            PoseidonHasher::hash_with_domain(domain::SMT_NODE, &data, Padding::Legacy)
+        );
+        assert_eq!(
+            tagged,
+            NativeHasher::hash_with_domain(domain::MERKLE_LEAF, &data, Padding::Legacy)
+        );
+        assert_eq!(
+            JubjubSignature::message_hash(HashVersion::V0, &data),
+            PoseidonHasher::hash(&data, Padding::Legacy)
+        );
+
+        // V0 keeps the untagged leaf hashes
+        let mut legacy = Merkle::new();
+        legacy.set(0, &data, Padding::Legacy, None);
+        let mut hash = [0; 4];
+        legacy.get(0, &mut [0; 8], &mut hash, Padding::Legacy);
+        assert_eq!(hash, PoseidonHasher::hash(&data, Padding::Legacy));
+
+        let mut merkle = Merkle::new().with_hash_version(HashVersion::V1);
+        merkle.set(0, &data, Padding::Legacy, None);
+        merkle.get(0, &mut [0; 8], &mut hash, Padding::Legacy);
+        assert_eq!(hash, tagged);
+        assert_eq!(
+            Merkle::load(merkle.root).try_get(0, &mut [0; 8], &mut hash, Padding::Legacy),
+            Err(ZkError::HashMismatch)
+        );
+
+        let mut v0 = KeyValueMap::new(Merkle::new());
+        let mut v1 = KeyValueMap::new(Merkle::new().with_hash_version(HashVersion::V1));
+        for key in [[1, 2, 3, 4], [1, 2, 3, 5], [(1 << 32) + 1, 2, 3, 4]] {
+            v0.set(&key, &[key[3]]);
+            v1.set(&key, &[key[3]]);
+        }
+        assert_ne!(v0.merkle().root, v1.merkle().root);
+        let mut buf = [0; 16];
+        assert_eq!(v1.get(&[(1 << 32) + 1, 2, 3, 4], &mut buf), 1);
+        assert_eq!(buf[0], 4);
+    }
+
+    #[test]
+    fn merkle_with_depth() {
+        use crate::check::{self, Checked};
+        use crate::offchain::merkle::MerkleDb;
+        use crate::{Merkle, EMPTY_ROOTS};
+        mock::reset();
+        check::reset();
+        let db = MerkleDb::new();
+        for (depth, root) in EMPTY_ROOTS.iter().enumerate() {
+            assert_eq!(db.empty_root_at(depth), *root);
+        }
+
+        let mut merkle = Merkle::<Checked>::with_depth_with_host(10);
+        merkle.set_simple(1023, &[1, 2, 3, 4], None);
+        merkle.set(5, &[5, 6, 7], Padding::Fixed3of4, None);
+        let mut leaf = [0; 4];
+        merkle.get_simple(1023, &mut leaf);
+        assert_eq!(leaf, [1, 2, 3, 4]);
+        assert_eq!(merkle.get(5, &mut [0; 4], &mut leaf, Padding::Fixed3of4), 3);
+        assert_eq!(check::take_violations(), vec![]);
+
+        let mut db = MerkleDb::new();
+        let root = db
+            .set_leaf_at(&EMPTY_ROOTS[10], 1023, 10, &[1, 2, 3, 4])
+            .unwrap();
+        let small = Merkle::load_with_depth(root, 10);
+        small.get_simple(1023, &mut leaf);
+        assert_eq!(leaf, [1, 2, 3, 4]);
+
+        let r = std::panic::catch_unwind(|| small.get_simple(1024, &mut [0; 4]));
+        assert!(r.is_err());
+        assert!(mock::debug_output().ends_with("error 7: leaf 1024 out of range of depth 10\n"));
+    }
+
+    #[test]
+    fn kvpair_over_small_tree() {
+        use crate::kvpair::{KeyValueMap, KeyValueMapU64};
+        use crate::Merkle;
+        mock::reset();
+        let mut kvpair = KeyValueMap::new(Merkle::with_depth(8));
+        let keys = [
+            [1, 0, 0, 0],
+            // same first group of 8 bits
+            [1 + (1 << 8), 0, 0, 0],
+            // differ in the group across the first two limbs
+            [1 + (1 << 62), 0, 0, 0],
+            [1 + (1 << 8), 1, 0, 0],
+            // differ in the last group only
+            [1, 0, 0, 1 << 63],
+        ];
+        for (i, key) in keys.iter().enumerate() {
+            kvpair.set(key, &[i as u64, 7]);
+        }
+        let mut buf = [0; 8];
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(kvpair.get(key, &mut buf), 2);
+            assert_eq!(buf[..2], [i as u64, 7]);
+        }
+        assert_eq!(kvpair.get(&[2, 0, 0, 0], &mut buf), 0);
+        assert_eq!(kvpair.get(&[1, 0, 0, 1], &mut buf), 0);
+
+        // groups of 10 bits straddle the limbs
+        let mut kvpair = KeyValueMap::new(Merkle::with_depth(10));
+        let keys = [[1, 0, 0, 0], [1, 1, 0, 0], [1, 0, 0, 1 << 63]];
+        for (i, key) in keys.iter().enumerate() {
+            kvpair.set(key, &[i as u64]);
+        }
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(kvpair.get(key, &mut buf), 1);
+            assert_eq!(buf[0], i as u64);
+        }
+
+        let r = std::panic::catch_unwind(|| {
+            KeyValueMapU64::new(Merkle::with_depth(8)).get(1);
+        });
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn merkle_update_pairs_one_get_with_one_set() {
+        use crate::check::{self, Checked};
+        use crate::kvpair::KeyValueMapU64;
+        use crate::Merkle;
+        mock::reset();
+        check::reset();
+        let trace = || unsafe { crate::wasm_trace_size() };
+        let mut merkle = Merkle::<Checked>::new_with_host();
+        let new = merkle.update_simple(3, |old| [old[0] + 1, 2, 3, 4]);
+        assert_eq!(new, [1, 2, 3, 4]);
+        merkle.update_simple(3, |old| [old[0] + 1, old[1], old[2], old[3]]);
+        let mut leaf = [0; 4];
+        merkle.get_simple(3, &mut leaf);
+        assert_eq!(leaf, [2, 2, 3, 4]);
+
+        merkle.update(4, Padding::Fixed3of4, |old| {
+            assert!(old.is_empty());
+            vec![7, 8]
+        });
+        let start = trace();
+        merkle.update(4, Padding::Fixed3of4, |old| {
+            old.iter().chain(&[9]).copied().collect()
+        });
+        let update_cost = trace() - start;
+        let mut data = [0; 4];
+        assert_eq!(merkle.get(4, &mut data, &mut leaf, Padding::Fixed3of4), 3);
+        assert_eq!(data[..3], [7, 8, 9]);
+
+        let mut kvpair = KeyValueMapU64::new(Merkle::<Checked>::new_with_host());
+        kvpair.set(1, 2);
+        kvpair.set(1, 3);
+        kvpair.set(1 << 32 | 1, 4);
+        assert_eq!(kvpair.get(1), 3);
+        assert_eq!(kvpair.get(1 << 32 | 1), 4);
+
+        // KeyValueMapU64::set reads the leaf once and writes it like update_simple, without the
+        // dummy get of set_simple on top of that read
+        let start = trace();
+        kvpair.set(2, 5);
+        assert_eq!(trace() - start, 29);
+        let start = trace();
+        kvpair.set(2, 6);
+        assert_eq!(trace() - start, 29);
+        let start = trace();
+        merkle.get_simple(6, &mut leaf);
+        merkle.set_simple(6, &[1, 0, 0, 0], None);
+        assert_eq!(trace() - start, 40);
+        assert_eq!(check::take_violations(), vec![]);
+
+        // a read followed by a set reads the leaf twice
+        let start = trace();
+        let mut old = [0; 8];
+        let len = merkle.get(4, &mut old, &mut leaf, Padding::Fixed3of4) as usize;
+        let mut new = old[..len].to_vec();
+        new.push(10);
+        merkle.set(4, &new, Padding::Fixed3of4, None);
+        assert!(trace() - start > update_cost);
+
+        let r = std::panic::catch_unwind(move || {
+            merkle.update_simple(5, |_| {
+                crate::PoseidonHasher::<Checked>::hash_with_host(&[1], Padding::None)
+            })
+        });
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn merkle_journal_batches_writes() {
+        use crate::check::{self, Checked};
+        use crate::{Merkle, MerkleJournal};
+        mock::reset();
+        check::reset();
+        let trace = || unsafe { crate::wasm_trace_size() };
+        let writes = [(3, 1), (5, 2), (3, 3), (3, 4), (5, 5), (9, 6)];
+
+        let start = trace();
+        let mut direct = Merkle::new();
+        for (index, v) in writes {
+            let mut leaf = [0; 4];
+            direct.get_simple(index, &mut leaf);
+            direct.set_simple(index, &[leaf[0] + v, 0, 0, 0], None);
+        }
+        let direct_cost = trace() - start;
+
+        let start = trace();
+        let mut journal = MerkleJournal::new(Merkle::<Checked>::new_with_host());
+        for (index, v) in writes {
+            let leaf = journal.get(index);
+            journal.set(index, &[leaf[0] + v, 0, 0, 0]);
+        }
+        assert_eq!(journal.pending(), 3);
+        assert_eq!(journal.commit(), direct.root);
+        // one host read per leaf and one write per written leaf, half of the direct accesses
+        assert!(trace() - start < direct_cost * 2 / 3);
+        assert_eq!(journal.get(3), [8, 0, 0, 0]);
+
+        journal.set(3, &[0; 4]);
+        journal.set(7, &[1; 4]);
+        journal.rollback();
+        assert_eq!(journal.pending(), 0);
+        assert_eq!(journal.get(3), [8, 0, 0, 0]);
+        assert_eq!(journal.get(7), [0; 4]);
+        assert_eq!(journal.finish().root, direct.root);
+        assert_eq!(check::take_violations(), vec![]);
+    }
+
+    #[test]
+    fn merkle_proof_from_native_tree() {
+        use crate::offchain::proof::PoseidonTree;
+        use crate::{MerkleProof, PoseidonHasher};
+        mock::reset();
+        let mut tree = PoseidonTree::new(8);
+        for i in [0u64, 1, 77, 255] {
+            tree.set(i, PoseidonHasher::hash(&[i], Padding::Fixed3of4));
+        }
+        let root = tree.root();
+        let proof = tree.proof(77);
+        assert_eq!(proof.siblings.len(), 8);
+        assert!(proof.verify(&root, 8));
+        assert!(tree.proof(3).verify(&root, 8));
+        assert!(!proof.verify(&root, 9));
+
+        let mut forged = proof.clone();
+        forged.index = 76;
+        assert!(!forged.verify(&root, 8));
+        forged.index = 77 + 256;
+        assert!(!forged.verify(&root, 8));
+        let forged = MerkleProof {
+            leaf: [0; 4],
+            ..proof.clone()
+        };
+        assert!(!forged.verify(&root, 8));
+
+        // the parent of leaves 76 and 77 passed off as a leaf one level up
+        let inner =
+            crate::proof::node_hash(&proof.siblings[0], &crate::proof::leaf_hash(&proof.leaf));
+        let forged = MerkleProof {
+            leaf: inner,
+            index: 77 >> 1,
+            siblings: proof.siblings[1..].to_vec(),
+        };
+        assert!(!forged.verify(&root, 8));
+        assert!(!forged.verify(&root, 7));
+        assert_ne!(forged.compute_root(), root);
+        assert_eq!(tree.proof(3).compute_root(), root);
+    }
+
+    #[test]
+    fn blob_streaming() {
+        use crate::blob::{self, CHUNK_LIMBS};
+        use crate::kvpair::KeyValueMap;
+        use crate::{BlobReader, Merkle, ZkError};
+        mock::reset();
+        let data: Vec<u64> = (0..3000).collect();
+        let mut kvpair = KeyValueMap::new(Merkle::new());
+        kvpair.set_blob(&[1, 2, 3, 4], &data);
+        kvpair.set(&[1, 2, 3, 5], &[7]);
+        assert!(kvpair.blob_reader(&[5, 5, 5, 5]).is_none());
+        // a long value of another key in the same slot is read whole, then found not to match
+        kvpair.set(&[6, 0, 0, 0], &data[..100]);
+        assert!(kvpair.blob_reader(&[6, 9, 9, 9]).is_none());
+        let mut reader = kvpair.blob_reader(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(reader.next_chunk().unwrap(), &data[..CHUNK_LIMBS]);
+        assert_eq!(reader.next(), Some(CHUNK_LIMBS as u64));
+        assert_eq!(reader.read_to_end(), data[CHUNK_LIMBS + 1..]);
+
+        let mut merkle = Merkle::new();
+        merkle.set_blob(9, &[]);
+        assert_eq!(merkle.blob_reader(9).count(), 0);
+        merkle.set_blob(9, &data[..10]);
+        assert_eq!(merkle.blob_reader(9).read_to_end(), data[..10]);
+
+        // a chunk that does not match the hash it is reached through is rejected
+        let head = blob::store_blob(&data[..10]);
+        let mut forged = vec![0; 4];
+        forged.extend_from_slice(&data[..9]);
+        crate::cache::store_data(&head, &forged);
+        let mut reader = BlobReader::open(head);
+        assert_eq!(reader.try_next_chunk(), Err(ZkError::HashMismatch));
+        let mut reader = BlobReader::open([1, 0, 0, 0]);
+        assert_eq!(reader.try_next_chunk(), Err(ZkError::MissingData));
+    }
+
+    #[test]
+    fn offchain_merkle_matches_host_default_root() {
+        // the root hard-coded in Merkle::new was produced by the zkWasm host
+        let db = crate::offchain::merkle::MerkleDb::new();
+        assert_eq!(db.empty_root(), crate::Merkle::new().root);
+    }
+
+    #[test]
+    fn offchain_merkle_db_history_and_paths() {
+        use crate::offchain::merkle::MerkleDb;
+        mock::reset();
+        let mut merkle = crate::Merkle::new();
+        merkle.set_simple(3, &[1, 2, 3, 4], None);
+        let first = merkle.root;
+        merkle.set_simple(7, &[5, 6, 7, 8], None);
+
+        // replaying the same host calls reproduces the guest roots
+        let path = std::env::temp_dir().join(format!("merkle-db-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut db = MerkleDb::open(&path).unwrap();
+        for (index, data) in [(3u64, [1u64, 2, 3, 4]), (7, [5, 6, 7, 8])] {
+            let root = db.session_root();
+            for r in root {
+                db.merkle_setroot(r);
+            }
+            db.merkle_address(index);
+            for d in data {
+                db.merkle_set(d);
+            }
+        }
+        let root: Vec<u64> = (0..4).map(|_| db.merkle_getroot()).collect();
+        assert_eq!(root, merkle.root);
+        drop(db);
+
+        let db = MerkleDb::open(&path).unwrap();
+        assert_eq!(db.get_leaf(&first, 3).unwrap(), [1, 2, 3, 4]);
+        assert_eq!(db.get_leaf(&first, 7).unwrap(), [0; 4]);
+        assert_eq!(db.get_leaf(&merkle.root, 7).unwrap(), [5, 6, 7, 8]);
+        let auth = db.auth_path(&merkle.root, 3).unwrap();
+        assert_eq!(auth.leaf, [1, 2, 3, 4]);
+        assert!(auth.verify(&merkle.root));
+        assert!(!auth.verify(&first));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "record-host-calls")]
+    #[test]
+    fn record_and_replay_host_calls() {
+        use crate::record::{self, HostOp, Replay};
+        use crate::Merkle;
+        mock::reset();
+        record::take_log();
+        // the default host records, no Recorder in the types
+        let mut merkle = Merkle::new();
+        merkle.set_simple(5, &[1, 2, 3, 4], None);
+        let len = record::log_len();
+        record::dump();
+        assert_eq!(record::log_len(), len);
+        assert!(mock::debug_output().starts_with("[{\"op\":\"merkle_address\",\"arg\":5}"));
+        let log = record::take_log();
+        assert_eq!(log[0].op, HostOp::MerkleAddress);
+        assert_eq!(log[0].arg, Some(5));
+        let bytes = record::encode(&log);
+        assert_eq!(record::decode(&bytes).unwrap(), log);
+        assert_eq!(
+            record::to_json(&log[..1]),
+            "[{\"op\":\"merkle_address\",\"arg\":5}]"
+        );
+
+        Replay::load(log.clone());
+        let mut replayed = Merkle::<Replay>::new_with_host();
+        replayed.set_simple(5, &[1, 2, 3, 4], None);
+        assert_eq!(replayed.root, merkle.root);
+        assert!(Replay::divergence().is_none() && Replay::finished());
+
+        Replay::load(log);
+        let mut replayed = Merkle::<Replay>::new_with_host();
+        replayed.set_simple(5, &[1, 2, 3, 5], None);
+        let divergence = Replay::divergence().unwrap();
+        assert_eq!(divergence.expected.unwrap().op, HostOp::MerkleSet);
+        assert_eq!(divergence.actual.arg, Some(5));
+    }
+
+    #[test]
+    fn checked_host_call_sequences() {
+        use crate::check::{self, Checked};
+        use crate::kvpair::KeyValueMap;
+        use crate::{BabyJubjubPoint, Merkle, PoseidonHasher};
+        mock::reset();
+        check::reset();
+        let mut kvpair = KeyValueMap::new(Merkle::<Checked>::new_with_host());
+        kvpair.set(&[1, 2, 3, 4], &[5, 6]);
+        kvpair.set(&[1, 2, 3, 5], &[7]);
+        let mut buf = [0; 16];
+        assert_eq!(kvpair.get(&[1, 2, 3, 4], &mut buf), 2);
+        PoseidonHasher::<Checked>::hash_with_host(&[1; 70], Padding::Legacy);
+        let p = BabyJubjubPoint {
+            x: primitive_types::U256([0, 0, 0, 0]),
+            y: primitive_types::U256([1, 0, 0, 0]),
+        };
+        BabyJubjubPoint::msm_with_host::<Checked>(&[(&p, &[1, 0, 0, 0]), (&p, &[2, 0, 0, 0])]);
+        assert_eq!(check::take_violations(), vec![]);
+
+        let mut merkle = Merkle::<Checked>::new_with_host();
+        let mut leaf = [0; 4];
+        merkle.get_simple(2, &mut leaf);
+        unsafe { merkle.set_simple_unsafe(3, &[1, 0, 0, 0]) };
+        let mut hasher = PoseidonHasher::<Checked>::new_with_host();
+        hasher.update(1);
+        // bypass the session guard, which would reject the overlap first
+        <Checked as crate::Host>::poseidon_new(1);
+        let violations = check::take_violations();
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].call, "merkle_set");
+        assert_eq!(violations[1].call, "poseidon_new");
+        assert_eq!(violations[1].index, violations[0].index + 4 + 4 + 2);
+
+        // a round is only read back once all of its limbs are pushed
+        let _ = std::panic::catch_unwind(<Checked as crate::Host>::poseidon_finalize);
+        let violations = check::take_violations();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].call, "poseidon_finalize");
+    }
+
+    #[test]
+    fn input_reader() {
+        use crate::InputReader;
+        mock::reset();
+        let hello = u64::from_le_bytes(*b"hello wo");
+        let rld = u64::from_le_bytes(*b"rld\0\0\0\0\0");
+        mock::set_public_inputs(&[7, u64::MAX, 1, 11, hello, rld]);
+        mock::set_private_inputs(&[3, 0, 0, 0, 5, 2, 0xabcd]);
+        let mut public = InputReader::public();
+        assert_eq!(public.read_u32(), 7);
+        assert_eq!(public.read_i64(), -1);
+        assert!(public.read_bool());
+        assert_eq!(public.read_string(16), "hello world");
+        let mut private = InputReader::private();
+        assert_eq!(private.read_field(), primitive_types::U256([3, 0, 0, 0]));
+        assert_eq!(private.read_u64_below(6), 5);
+        assert_eq!(private.read_bytes(2), vec![0xcd, 0xab]);
+    }
+
+    #[test]
+    #[should_panic(expected = "require failed")]
+    fn input_reader_range_check() {
+        mock::reset();
+        mock::set_public_inputs(&[2]);
+        crate::InputReader::public().read_bool();
+    }
+
+    #[test]
+    #[should_panic(expected = "require failed")]
+    fn input_reader_signature_scalar_range_check() {
+        mock::reset();
+        let mut inputs = vec![0, 0, 0, 0, 1, 0, 0, 0];
+        inputs.extend_from_slice(&crate::jubjub::SUBGROUP_ORDER);
+        mock::set_public_inputs(&inputs);
+        crate::InputReader::public().read_signature();
+    }
+
+    #[test]
+    fn output_writer() {
+        use crate::OutputWriter;
+        mock::reset();
+        let mut direct = OutputWriter::direct();
+        direct.write_u64(1);
+        direct.write_limbs(&[2, 3, 4, 5]);
+        assert_eq!(direct.finish(), None);
+        assert_eq!(mock::outputs(), vec![1, 2, 3, 4, 5]);
+
+        mock::reset();
+        let mut commit = OutputWriter::commit();
+        let values: Vec<u64> = (0..40).collect();
+        commit.write_slice(&values);
+        let digest = commit.finish().unwrap();
+        assert_eq!(mock::outputs(), digest);
+        assert_eq!(crate::offchain::output_digest(&values), digest);
+
+        // trailing zeros are not lost in the padding
+        mock::reset();
+        let mut commit = OutputWriter::commit();
+        commit.write_slice(&[1, 2, 3]);
+        let digest = commit.finish().unwrap();
+        let mut commit = OutputWriter::commit();
+        commit.write_slice(&[1, 2, 3, 0]);
+        assert_ne!(commit.finish().unwrap(), digest);
+        assert_ne!(
+            crate::offchain::output_digest(&[1, 2, 3]),
+            crate::offchain::output_digest(&[1, 2, 3, 0])
+        );
+    }
+
+    #[test]
+    fn context_roundtrip() {
+        use crate::kvpair::KeyValueMap;
+        use crate::{ContextReader, ContextWriter, Merkle};
+        mock::reset();
+        let mut kvpair = KeyValueMap::new(Merkle::new());
+        kvpair.set(&[1, 2, 3, 4], &[42]);
+        let mut w = ContextWriter::new(3);
+        w.write(&kvpair);
+        w.write(&7u64);
+        w.write(&true);
+        w.write(&vec![9u64, 10]);
+        w.finish();
+
+        let context = mock::written_context();
+        mock::set_context(&context);
+        let mut r = ContextReader::load(3);
+        let kvpair: KeyValueMap<Merkle> = r.read();
+        assert_eq!(r.read::<u64>(), 7);
+        assert!(r.read::<bool>());
+        assert_eq!(r.read::<Vec<u64>>(), vec![9, 10]);
+        assert_eq!(r.remaining(), 0);
+        let mut buf = [0; 8];
+        assert_eq!(kvpair.get(&[1, 2, 3, 4], &mut buf), 1);
+        assert_eq!(buf[0], 42);
+
+        let mut tampered = context;
+        tampered[3] ^= 1;
+        mock::set_context(&tampered);
+        let r = std::panic::catch_unwind(|| ContextReader::load(3));
+        assert!(r.is_err());
+
+        // the length is checked before anything is buffered
+        mock::set_context(&[3, u64::MAX]);
+        let r = std::panic::catch_unwind(|| ContextReader::load(3));
+        assert_eq!(r.err().unwrap().downcast_ref(), Some(&"require failed"));
+    }
+
+    #[test]
+    fn context_rejects_unreduced_limbs() {
+        use crate::jubjub::MODULUS;
+        use crate::{ContextReader, ContextWriter};
+        mock::reset();
+        let mut w = ContextWriter::new(3);
+        for _ in 0..6 {
+            w.write_u64(0);
+        }
+        w.finish();
+
+        // payload limbs 2..6 rewritten to the modulus, which is zero in the field
+        let mut tampered = mock::written_context();
+        tampered[4..8].copy_from_slice(&MODULUS);
+        mock::set_context(&tampered);
+        let r = std::panic::catch_unwind(|| ContextReader::load(3));
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn context_keeps_merkle_config() {
+        use crate::poseidon::HashVersion;
+        use crate::{ContextReader, ContextWriter, Merkle};
+        mock::reset();
+        let mut merkle = Merkle::with_depth(10).with_hash_version(HashVersion::V1);
+        merkle.set(3, &[5, 6], Padding::Fixed3of4, None);
+        let mut w = ContextWriter::new(1);
+        w.write(&merkle);
+        w.finish();
+
+        mock::set_context(&mock::written_context());
+        let merkle: Merkle = ContextReader::load(1).read();
+        assert_eq!(merkle.depth(), 10);
+        assert_eq!(merkle.hash_version(), HashVersion::V1);
+        let mut leaf = [0; 2];
+        merkle.get(3, &mut leaf, &mut [0; 4], Padding::Fixed3of4);
+        assert_eq!(leaf, [5, 6]);
+    }
+
+    #[test]
+    fn fallible_checks() {
+        use crate::{BabyJubjubPoint, JubjubSignature, Merkle, ZkError};
+        mock::reset();
+        let mut merkle = Merkle::new();
+        merkle.set(0, &[1, 2, 3], Padding::None, None);
+        let mut hash = [0; 4];
+        assert_eq!(
+            merkle.try_get(0, &mut [0; 2], &mut hash, Padding::None),
+            Err(ZkError::BufferTooSmall {
+                len: 3,
+                capacity: 2
+            })
+        );
+        assert_eq!(
+            merkle.try_get(0, &mut [0; 3], &mut hash, Padding::None),
+            Ok(3)
+        );
+        let point = BabyJubjubPoint {
+            x: primitive_types::U256([0, 0, 0, 0]),
+            y: primitive_types::U256([1, 0, 0, 0]),
+        };
+        let sig = JubjubSignature {
+            sig_r: point.clone(),
+            sig_s: [1, 0, 0, 0],
+        };
+        assert_eq!(
+            sig.try_verify(&point, &[1, 0, 0, 0]),
+            Err(ZkError::InvalidSignature)
+        );
+
+        let r = std::panic::catch_unwind(|| crate::ensure!(1 + 1 == 3, 42, "bad sum {}", 3));
+        assert!(r.is_err());
+        assert!(mock::debug_output().ends_with("error 42: bad sum 3\n"));
+    }
+
+    #[cfg(not(feature = "no-panic-hook"))]
+    #[test]
+    fn panic_hook_reports_location() {
+        mock::reset();
+        crate::panic_hook::install();
+        let r = std::panic::catch_unwind(|| {
+            let v: Vec<u64> = vec![];
+            v[std::hint::black_box(3)]
+        });
+        let _ = std::panic::take_hook();
+        assert!(r.is_err());
+        let out = mock::debug_output();
+        assert!(out.starts_with("panicked at src/test.rs:"));
+        assert!(out.ends_with("index out of bounds: the len is 0 but the index is 3\n"));
+    }
+
+    #[cfg(feature = "no-panic-hook")]
+    #[test]
+    fn panic_hook_stripped() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        static CALLED: AtomicBool = AtomicBool::new(false);
+        mock::reset();
+        std::panic::set_hook(Box::new(|_| CALLED.store(true, Ordering::SeqCst)));
+        crate::panic_hook::install();
+        let r = std::panic::catch_unwind(|| panic!("stripped"));
+        let _ = std::panic::take_hook();
+        assert!(r.is_err());
+        assert!(CALLED.load(Ordering::SeqCst));
+        assert_eq!(mock::debug_output(), "");
+    }
+
+    #[cfg(not(feature = "no-debug"))]
+    #[test]
+    fn logger_levels_and_fields() {
+        use crate::logger::hex;
+        mock::reset();
+        let root = [1, 0, 0, 0xff];
+        crate::log_info!("set leaf {}", 3; root = hex(&root), len = 2);
+        crate::log_trace!("not shown");
+        crate::dbgln!("x = {}", 1 + 1);
+        assert_eq!(
+            mock::debug_output(),
+            "[INFO] set leaf 3 root=0x00000000000000ff000000000000000000000000000000000000000000000001 len=2\nx = 2\n"
+        );
+    }
+
+    #[test]
+    fn trace_budget_stops_before_limit() {
+        use crate::kvpair::KeyValueMapU64;
+        use crate::{Merkle, TraceBudget};
+        mock::reset();
+        let mut kvpair = KeyValueMapU64::new(Merkle::new());
+        let limit = 2000;
+        let mut budget = TraceBudget::new(limit).with_reserve(10);
+        let mut key = 0;
+        while budget.next_item() {
+            kvpair.set(key, key + 1);
+            key += 1;
+            budget.item_done();
+        }
+        assert!(key > 0);
+        assert!(budget.used() + 10 <= limit);
+        assert!(budget.remaining() < budget.max_item_cost() + 10);
+        assert_eq!(budget.finish(), key);
+        assert_eq!(mock::outputs(), vec![key]);
+
+        // breaking out when the input runs out does not count the admitted item
+        let mut items = [1u64, 2, 3].into_iter();
+        let mut budget = TraceBudget::new(limit);
+        while budget.next_item() {
+            let Some(item) = items.next() else { break };
+            kvpair.set(item, item);
+            budget.item_done();
+        }
+        assert_eq!(budget.finish(), 3);
+
+        // nothing is admitted, not even a first item, without rows left above the reserve; the
+        // trace size read by next_item is the one row used here
+        let mut budget = TraceBudget::new(limit).with_reserve(limit - 1);
+        assert!(!budget.next_item());
+        let mut budget = TraceBudget::new(1);
+        assert!(!budget.next_item());
+        assert_eq!(budget.finish(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "require failed")]
+    fn jubjub_rejects_bad_signature() {
+        mock::reset();
+        let sig = crate::JubjubSignature {
+            sig_r: crate::BabyJubjubPoint {
+                x: primitive_types::U256([0, 0, 0, 0]),
+                y: primitive_types::U256([1, 0, 0, 0]),
+            },
+            sig_s: [1, 0, 0, 0],
+        };
+        sig.verify(&sig.sig_r.clone(), &[1, 0, 0, 0]);
+    }
 }
@@ -0,0 +1,66 @@
+use crate::host::{Host, ZkWasmHost};
+use crate::jubjub::{BabyJubjubPoint, MODULUS};
+use crate::poseidon::{domain, Padding, PoseidonHasher};
+use primitive_types::U256;
+use std::marker::PhantomData;
+
+/// Fiat-Shamir transcript over the host poseidon hasher.
+///
+/// Appended values are buffered; `challenge_scalar` hashes the previous challenge state with
+/// everything appended since, in the `TRANSCRIPT` domain with `Padding::LengthPrefixed`, and
+/// the digest becomes the new state. Like `OutputWriter`, nothing is hashed while values are
+/// appended, so other host functions can be used in between.
+///
+/// Provers derive the same challenges with `offchain::transcript::Transcript`.
+pub struct Transcript<H: Host = ZkWasmHost> {
+    state: [u64; 4],
+    pending: Vec<u64>,
+    host: PhantomData<H>,
+}
+
+impl Transcript {
+    pub fn new(label: u64) -> Self {
+        Self::new_with_host(label)
+    }
+}
+
+impl<H: Host> Transcript<H> {
+    /// Transcript of the protocol identified by `label`
+    pub fn new_with_host(label: u64) -> Self {
+        Transcript {
+            state: [0; 4],
+            pending: vec![label],
+            host: PhantomData,
+        }
+    }
+
+    pub fn append_u64(&mut self, v: u64) {
+        self.pending.push(v);
+    }
+
+    pub fn append_hash(&mut self, hash: &[u64; 4]) {
+        self.pending.extend_from_slice(hash);
+    }
+
+    pub fn append_point(&mut self, p: &BabyJubjubPoint) {
+        self.pending.extend_from_slice(&p.x.0);
+        self.pending.extend_from_slice(&p.y.0);
+    }
+
+    /// Challenge bound to everything appended so far, reduced modulo `MODULUS`
+    pub fn challenge_scalar(&mut self) -> [u64; 4] {
+        let mut input = self.state.to_vec();
+        input.append(&mut self.pending);
+        self.state = PoseidonHasher::<H>::hash_with_domain_with_host(
+            domain::TRANSCRIPT,
+            &input,
+            Padding::LengthPrefixed,
+        );
+        reduce(&self.state)
+    }
+}
+
+/// `v` modulo the jubjub scalar `MODULUS`
+pub fn reduce(v: &[u64; 4]) -> [u64; 4] {
+    (U256(*v) % U256(MODULUS)).0
+}
@@ -1,3 +1,4 @@
+#[cfg(not(feature = "mock-host"))]
 extern "C" {
     /// inserts a witness at the current wasm_private inputs cursor
     pub fn wasm_witness_insert(u: u64);
@@ -9,6 +10,13 @@ extern "C" {
     pub fn require(cond: bool);
 }
 
+#[cfg(feature = "mock-host")]
+pub use crate::mock::{
+    require, wasm_witness_indexed_insert, wasm_witness_indexed_pop, wasm_witness_indexed_push,
+    wasm_witness_insert, wasm_witness_pop, wasm_witness_set_index,
+};
+
+use crate::error::ZkError;
 use std::alloc::{GlobalAlloc, Layout, System};
 use std::mem::size_of;
 use std::ptr::null_mut;
@@ -138,9 +146,9 @@ impl<T: WitnessObjReader> WitnessObjReader for Vec<T> {
             let offset = arr_ptr as *mut T;
             let start = arr_ptr as usize;
             let mem_len = len * size_of::<T>();
-            require(start >= WITNESS_AREA);
-            require(mem_len < MAX_WITNESS_OBJ_SIZE);
-            require(start + len <= WITNESS_AREA_END);
+            crate::ensure!(start >= WITNESS_AREA, ZkError::WitnessOutOfBounds);
+            crate::ensure!(mem_len < MAX_WITNESS_OBJ_SIZE, ZkError::WitnessOutOfBounds);
+            crate::ensure!(start + len <= WITNESS_AREA_END, ZkError::WitnessOutOfBounds);
             for i in 0..len {
                 //T::from_witness(unsafe { offset.add(i) as *mut T });
                 *(offset as *mut u64).add(i) = wasm_witness_pop();
@@ -179,8 +187,8 @@ fn load_witness_obj_inner<Obj: Clone + WitnessObjReader + WitnessObjWriter>(
     let obj_start = base as usize + obj_offset;
     let obj_end = obj_start + obj_offset;
     unsafe {
-        require(obj_start >= WITNESS_AREA);
-        require(obj_end <= WITNESS_AREA_END);
+        crate::ensure!(obj_start >= WITNESS_AREA, ZkError::WitnessOutOfBounds);
+        crate::ensure!(obj_end <= WITNESS_AREA_END, ZkError::WitnessOutOfBounds);
     }
     let obj = obj_start as *mut Obj;
     Obj::from_witness(obj);
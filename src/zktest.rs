@@ -0,0 +1,49 @@
+//! Runtime side of the `#[zktest]` harness.
+
+pub struct ZkTest {
+    pub name: &'static str,
+    pub run: fn(),
+}
+
+/// Run one test in the guest. A failing test aborts the proof, so only passing tests report.
+pub fn run(name: &str, f: fn()) -> i64 {
+    crate::dbg!("test {} ...\n", name);
+    f();
+    crate::dbg!("test {} ok\n", name);
+    0
+}
+
+/// Run all tests in the guest, the body of the generated `zkmain`, and dump the profiled scopes
+pub fn run_all(tests: &[ZkTest]) -> i64 {
+    for t in tests {
+        run(t.name, t.run);
+    }
+    crate::dbg!("{} tests passed\n", tests.len());
+    crate::profile::dump();
+    0
+}
+
+/// Run one test natively, against a fresh mock host when `mock-host` is enabled
+pub fn run_native(_name: &str, f: fn()) {
+    #[cfg(feature = "mock-host")]
+    crate::mock::reset();
+    f();
+}
+
+/// Run all tests natively, each isolated in its own unwind boundary, and print a report.
+/// Returns the name and outcome of every test.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_all_native(tests: &[ZkTest]) -> Vec<(&'static str, bool)> {
+    tests
+        .iter()
+        .map(|t| {
+            let passed = std::panic::catch_unwind(|| run_native(t.name, t.run)).is_ok();
+            println!(
+                "zktest {} ... {}",
+                t.name,
+                if passed { "ok" } else { "FAILED" }
+            );
+            (t.name, passed)
+        })
+        .collect()
+}
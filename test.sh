@@ -6,7 +6,7 @@ set -x
 rm -rf output
 mkdir output
 
-FUNC=zkmain
+FUNC=${FUNC:-zkmain} # or zktest_<name> to prove a single #[zktest]
 
 # Single test
 ~/zkWasm/target/release/delphinus-cli --host standard -k 22 --function $FUNC --output ./output --param ./output --wasm ./pkg/output.wasm setup